@@ -0,0 +1,1745 @@
+// rax25kb - AX.25 KISS Bridge
+//
+// Copyright (C) 2025-2026 Kris Kirby, KE4AHR
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+//
+// Configuration file reader and validation
+//
+// This file is part of rax25kb.
+//
+// rax25kb is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rax25kb is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rax25kb.  If not, see <https://www.gnu.org/licenses/>.
+//
+// The file is read into entries that remember the file and line each
+// key=value came from. Files ending in .toml use the structured format
+// instead: globals at the top level and one [[cross_connect]] table per
+// bridge, flattened into the same entries (cross_connectNNNN.param, numbered
+// in file order) so both formats share one validator and one Config builder.
+// include= splices other files in where it appears, and templates
+// (template.NAME.param) give their parameters to every cross-connect that
+// names them with cross_connectNNNN.template, unless it sets them itself.
+// Values may refer to the environment (${VAR}, ${VAR:-default}) or be read
+// from a file (@file:PATH, for secrets); interpolate() expands them before
+// validation.
+// Validation runs in two passes: check_entries()
+// looks at every entry on its own (syntax, known keys, value ranges and
+// enums) and drops those it reports, then check_config() looks at the Config
+// built from the rest as a whole (duplicate binds, AGW clashes, secondaries
+// without a primary). Both always run and every problem found is reported;
+// nothing is silently replaced by a default.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml_edit::{Document, Item, TableLike, Value};
+
+use crate::{Config, CrossConnect, DataBits, FlowControl, ParamPolicy, Parity, StopBits, TcpMode};
+
+// One key=value from a configuration file
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    pub value: String,
+    pub file: String,
+    pub line: usize,
+    pub secret: Option<String>,     // The @file:PATH the value was read from; never shown
+}
+
+// A problem found in the configuration. Line 0 means the file as a whole.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn at(entry: &Entry, message: String) -> Self {
+        Diagnostic { file: entry.file.clone(), line: entry.line, message }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}:{}: {}", self.file, self.line, self.message)
+        } else {
+            write!(f, "{}: {}", self.file, self.message)
+        }
+    }
+}
+
+// All problems that stopped a configuration from loading
+#[derive(Debug)]
+pub struct ConfigError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        let count = self.diagnostics.len();
+        write!(f, "{} configuration error{}", count, if count == 1 { "" } else { "s" })
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<Vec<Diagnostic>> for ConfigError {
+    fn from(diagnostics: Vec<Diagnostic>) -> Self {
+        ConfigError { diagnostics }
+    }
+}
+
+// What a key's value must look like
+#[derive(Clone, Copy)]
+enum Kind {
+    Bool,
+    Int(i64, i64),
+    Choice(&'static [&'static str]),
+    Text,
+}
+
+const BOOL_VALUES: &[&str] = &["yes", "no", "true", "false", "1", "0"];
+
+const GLOBAL_KEYS: &[(&str, Kind)] = &[
+    ("log_level", Kind::Int(0, 9)),
+    ("logfile", Kind::Text),
+    ("pidfile", Kind::Text),
+    ("log_to_console", Kind::Bool),
+    ("log_to_file_only", Kind::Bool),
+    ("quiet_startup", Kind::Bool),
+    ("pcap_file", Kind::Text),
+    ("max_tcp_clients", Kind::Int(1, 1000)),
+    ("agw_server_enable", Kind::Bool),
+    ("agw_server_address", Kind::Text),
+    ("agw_server_port", Kind::Int(1, 65535)),
+    ("agw_max_clients", Kind::Int(1, 1000)),
+];
+
+const CROSS_CONNECT_KEYS: &[(&str, Kind)] = &[
+    ("name", Kind::Text),
+    ("template", Kind::Text),
+    ("serial_port", Kind::Text),
+    ("baud_rate", Kind::Int(50, 4_000_000)),
+    ("flow_control", Kind::Choice(&["none", "off", "no", "software", "xon", "xonxoff", "xon-xoff",
+        "hardware", "rtscts", "rts-cts", "rts/cts", "dtrdsr", "dtr-dsr", "dtr/dsr"])),
+    ("stop_bits", Kind::Choice(&["1", "2", "one", "two"])),
+    ("data_bits", Kind::Choice(&["7", "8", "seven", "eight"])),
+    ("parity", Kind::Choice(&["none", "n", "no", "odd", "o", "even", "e"])),
+    ("init_script", Kind::Text),
+    ("exit_script", Kind::Text),
+    ("tcp_address", Kind::Text),
+    ("tcp_port", Kind::Int(1, 65535)),
+    ("tcp_mode", Kind::Choice(&["server", "client", "none"])),
+    ("tcp_server_address", Kind::Text),
+    ("tcp_server_port", Kind::Int(1, 65535)),
+    ("pty_link", Kind::Text),
+    ("kiss_port", Kind::Int(0, 15)),
+    ("kiss_chan", Kind::Int(-1, 15)),
+    ("kiss_copy", Kind::Bool),
+    ("xkiss_mode", Kind::Bool),
+    ("xkiss_port", Kind::Int(0, 15)),
+    ("xkiss_checksum", Kind::Bool),
+    ("xkiss_polling", Kind::Bool),
+    ("xkiss_poll_timer_ms", Kind::Int(1, 60000)),
+    ("xkiss_rx_buffer_size", Kind::Int(4096, 1048576)),
+    ("xkiss_emulation", Kind::Bool),
+    ("ackmode_emulation", Kind::Bool),
+    ("txdelay", Kind::Int(0, 255)),
+    ("persist", Kind::Int(0, 255)),
+    ("slottime", Kind::Int(0, 255)),
+    ("txtail", Kind::Int(0, 255)),
+    ("fullduplex", Kind::Bool),
+    ("kiss_param_interval", Kind::Int(0, 86400)),
+    ("kiss_param_policy", Kind::Choice(&["pass", "override", "block"])),
+    ("kiss_command_filter", Kind::Text),
+    ("kiss_client_filter", Kind::Text),
+    ("serial_to_serial", Kind::Text),
+    ("tcp_to_tcp_dangerous", Kind::Bool),
+    ("tcp_to_tcp_also_dangerous", Kind::Bool),
+    ("phil_flag", Kind::Bool),
+    ("dump", Kind::Bool),
+    ("parse_kiss", Kind::Bool),
+    ("dump_ax25", Kind::Bool),
+    ("raw_copy", Kind::Bool),
+    ("reframe_large_packets", Kind::Bool),
+    ("max_frame_len", Kind::Int(64, 65536)),
+    ("agw_port", Kind::Int(0, 255)),
+    ("agw_enable", Kind::Bool),
+];
+
+// Cross-connect keys also accepted at top level in legacy (single bridge) mode
+const LEGACY_KEYS: &[&str] = &[
+    "serial_port", "baud_rate", "flow_control", "stop_bits", "data_bits", "parity",
+    "tcp_address", "tcp_port", "tcp_mode", "tcp_server_address", "tcp_server_port",
+    "xkiss_rx_buffer_size", "xkiss_emulation", "ackmode_emulation",
+    "tcp_to_tcp_dangerous", "tcp_to_tcp_also_dangerous", "phil_flag", "dump",
+    "parse_kiss", "dump_ax25", "raw_copy", "reframe_large_packets", "max_frame_len",
+];
+
+// Structured format: (section of the [[cross_connect]] or [template.NAME]
+// table, key, flat parameter). Section "" is the table itself.
+const TOML_KEYS: &[(&str, &str, &str)] = &[
+    ("", "name", "name"),
+    ("", "template", "template"),
+    ("", "phil_flag", "phil_flag"),
+    ("", "dump", "dump"),
+    ("", "parse_kiss", "parse_kiss"),
+    ("", "dump_ax25", "dump_ax25"),
+    ("", "raw_copy", "raw_copy"),
+    ("", "reframe_large_packets", "reframe_large_packets"),
+    ("serial", "port", "serial_port"),
+    ("serial", "baud_rate", "baud_rate"),
+    ("serial", "flow_control", "flow_control"),
+    ("serial", "stop_bits", "stop_bits"),
+    ("serial", "data_bits", "data_bits"),
+    ("serial", "parity", "parity"),
+    ("serial", "peer", "serial_to_serial"),
+    ("serial", "init_script", "init_script"),
+    ("serial", "exit_script", "exit_script"),
+    ("tcp", "mode", "tcp_mode"),
+    ("tcp", "address", "tcp_address"),
+    ("tcp", "port", "tcp_port"),
+    ("tcp", "server_address", "tcp_server_address"),
+    ("tcp", "server_port", "tcp_server_port"),
+    ("tcp", "to_tcp_dangerous", "tcp_to_tcp_dangerous"),
+    ("tcp", "to_tcp_also_dangerous", "tcp_to_tcp_also_dangerous"),
+    ("pty", "link", "pty_link"),
+    ("kiss", "port", "kiss_port"),
+    ("kiss", "chan", "kiss_chan"),
+    ("kiss", "copy", "kiss_copy"),
+    ("kiss", "max_frame_len", "max_frame_len"),
+    ("kiss", "ackmode_emulation", "ackmode_emulation"),
+    ("kiss", "txdelay", "txdelay"),
+    ("kiss", "persist", "persist"),
+    ("kiss", "slottime", "slottime"),
+    ("kiss", "txtail", "txtail"),
+    ("kiss", "fullduplex", "fullduplex"),
+    ("kiss", "param_interval", "kiss_param_interval"),
+    ("kiss", "param_policy", "kiss_param_policy"),
+    ("kiss", "command_filter", "kiss_command_filter"),
+    ("kiss", "client_filter", "kiss_client_filter"),
+    ("xkiss", "mode", "xkiss_mode"),
+    ("xkiss", "port", "xkiss_port"),
+    ("xkiss", "checksum", "xkiss_checksum"),
+    ("xkiss", "polling", "xkiss_polling"),
+    ("xkiss", "poll_timer_ms", "xkiss_poll_timer_ms"),
+    ("xkiss", "rx_buffer_size", "xkiss_rx_buffer_size"),
+    ("xkiss", "emulation", "xkiss_emulation"),
+    ("agw", "enable", "agw_enable"),
+    ("agw", "port", "agw_port"),
+];
+
+fn kind_of(table: &[(&str, Kind)], key: &str) -> Option<Kind> {
+    table.iter().find(|(name, _)| *name == key).map(|&(_, kind)| kind)
+}
+
+// Split "cross_connectNNNN.param" into ("cross_connectNNNN", "param")
+pub fn split_cross_connect_key(key: &str) -> Option<(&str, &str)> {
+    let (id, param) = key.split_once('.')?;
+    let digits = id.strip_prefix("cross_connect")?;
+    if digits.len() == 4 && digits.bytes().all(|b| b.is_ascii_digit()) && !param.is_empty() {
+        Some((id, param))
+    } else {
+        None
+    }
+}
+
+// Split "template.NAME.param" into ("NAME", "param"). Names are letters,
+// digits, '-' and '_'.
+fn split_template_key(key: &str) -> Option<(&str, &str)> {
+    let (name, param) = key.strip_prefix("template.")?.split_once('.')?;
+    let valid = !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    (valid && !param.is_empty()).then_some((name, param))
+}
+
+// Read a configuration file, and the files it includes, into entries.
+// Lines that are not key=value are reported in `diagnostics` and skipped.
+pub fn read_file(path: &str, diagnostics: &mut Vec<Diagnostic>) -> Result<Vec<Entry>, ConfigError> {
+    let entries = read_tree(Path::new(path), &mut Vec::new(), &mut 0, diagnostics)
+        .map_err(|message| ConfigError::from(vec![Diagnostic {
+            file: path.to_string(),
+            line: 0,
+            message,
+        }]))?;
+    if entries.is_empty() && diagnostics.is_empty() {
+        diagnostics.push(Diagnostic {
+            file: path.to_string(),
+            line: 0,
+            message: "no settings found".to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+// Read one file, replacing each include= with the entries of the files it
+// names. `stack` holds the files being read, to catch include cycles, and
+// `next_slot` the first cross_connectNNNN number not used yet, where the
+// [[cross_connect]] tables of the next structured file start.
+fn read_tree(path: &Path, stack: &mut Vec<PathBuf>, next_slot: &mut usize,
+             diagnostics: &mut Vec<Diagnostic>) -> Result<Vec<Entry>, String> {
+    let file = path.to_string_lossy();
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read config file: {}", e))?;
+    let parsed = if file.ends_with(".toml") {
+        parse_toml(&file, &contents, next_slot, diagnostics)
+    } else {
+        parse(&file, &contents, diagnostics)
+    };
+    for entry in &parsed {
+        let number = split_cross_connect_key(&entry.key)
+            .and_then(|(slot, _)| slot["cross_connect".len()..].parse::<usize>().ok());
+        if let Some(number) = number {
+            *next_slot = (*next_slot).max(number + 1);
+        }
+    }
+
+    stack.push(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+    let mut entries = Vec::new();
+    for entry in parsed {
+        if entry.key != "include" {
+            entries.push(entry);
+            continue;
+        }
+        let paths = match include_paths(&entry.value, path.parent().unwrap_or(Path::new(""))) {
+            Ok(paths) => paths,
+            Err(message) => {
+                diagnostics.push(Diagnostic::at(&entry, format!("include {}: {}", entry.value, message)));
+                continue;
+            }
+        };
+        for included in paths {
+            let canonical = included.canonicalize().unwrap_or_else(|_| included.clone());
+            if stack.contains(&canonical) {
+                diagnostics.push(Diagnostic::at(&entry, format!(
+                    "include {}: {} is already being read (include cycle)", entry.value, included.display())));
+                continue;
+            }
+            match read_tree(&included, stack, next_slot, diagnostics) {
+                Ok(more) => entries.extend(more),
+                Err(message) => diagnostics.push(Diagnostic::at(&entry, format!(
+                    "include {}: {}", included.display(), message))),
+            }
+        }
+    }
+    stack.pop();
+    Ok(entries)
+}
+
+// Files named by an include= value: a path, or a directory and a file name
+// pattern with * and ?. Relative paths start at the including file's
+// directory. Pattern matches are read in name order; a pattern that matches
+// nothing includes nothing.
+fn include_paths(pattern: &str, base: &Path) -> Result<Vec<PathBuf>, String> {
+    if pattern.is_empty() {
+        return Err("empty path".to_string());
+    }
+    let path = base.join(pattern);
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        return Ok(vec![path]);
+    }
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if dir.to_string_lossy().contains(['*', '?']) {
+        return Err("wildcards are only supported in the file name".to_string());
+    }
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| format!("{}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.file_name()
+            .is_some_and(|file| wildcard_match(&name, &file.to_string_lossy())))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+// Shell-style match of a file name: * is any run of characters, ? one
+// character. Hidden files only match a pattern that starts with '.'.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;  // Last * and where its match ends
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            star = Some((star_p, star_n + 1));
+            p = star_p + 1;
+            n = star_n + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn parse(file: &str, contents: &str, diagnostics: &mut Vec<Diagnostic>) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (index, raw) in contents.lines().enumerate() {
+        let line = index + 1;
+        let text = raw.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let problem = |message: String| Diagnostic { file: file.to_string(), line, message };
+        let Some((key, value)) = text.split_once('=') else {
+            diagnostics.push(problem(format!("expected key=value, found '{}'", text)));
+            continue;
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            diagnostics.push(problem("missing key before '='".to_string()));
+            continue;
+        }
+        match parse_value(value.trim()) {
+            Ok(value) => entries.push(Entry {
+                key: key.to_string(),
+                value,
+                file: file.to_string(),
+                line,
+                secret: None,
+            }),
+            Err(message) => diagnostics.push(problem(format!("{}: {}", key, message))),
+        }
+    }
+    entries
+}
+
+// Value after '=': either "quoted" or bare, optionally followed by a # comment.
+// In a bare value '#' only starts a comment at the start or after whitespace.
+fn parse_value(value: &str) -> Result<String, String> {
+    if let Some(quoted) = value.strip_prefix('"') {
+        let end = quoted.find('"').ok_or("unterminated quoted value")?;
+        let rest = quoted[end + 1..].trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(format!("unexpected text after quoted value: '{}'", rest));
+        }
+        return Ok(quoted[..end].to_string());
+    }
+    let mut end = value.len();
+    let mut previous = ' ';
+    for (i, c) in value.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            end = i;
+            break;
+        }
+        previous = c;
+    }
+    Ok(value[..end].trim_end().to_string())
+}
+
+fn line_at(contents: &str, offset: usize) -> usize {
+    contents[..offset].matches('\n').count() + 1
+}
+
+fn line_of(contents: &str, item: &Item) -> usize {
+    item.span().map(|span| line_at(contents, span.start)).unwrap_or(0)
+}
+
+// Structured format value as the flat format would spell it
+fn toml_value(item: &Item) -> Option<String> {
+    match item.as_value()? {
+        Value::String(s) => Some(s.value().clone()),
+        Value::Integer(i) => Some(i.value().to_string()),
+        Value::Boolean(b) => Some(if *b.value() { "yes" } else { "no" }.to_string()),
+        _ => None,
+    }
+}
+
+fn toml_unknown(key: &str) -> String {
+    let flat = key.rsplit('.').next().unwrap_or(key);
+    match TOML_KEYS.iter().find(|(section, _, f)| *f == flat && !section.is_empty()) {
+        Some((section, name, _)) => format!(
+            "unknown cross-connect setting '{}' (use {} in [cross_connect.{}])", key, name, section),
+        None => format!("unknown cross-connect setting '{}'", key),
+    }
+}
+
+// Collects the entries of one structured file
+struct TomlReader<'a> {
+    file: &'a str,
+    contents: &'a str,
+    entries: Vec<Entry>,
+    diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+impl TomlReader<'_> {
+    fn problem(&mut self, line: usize, message: String) {
+        self.diagnostics.push(Diagnostic { file: self.file.to_string(), line, message });
+    }
+
+    fn push(&mut self, key: String, item: &Item) {
+        let line = line_of(self.contents, item);
+        match toml_value(item) {
+            Some(value) => self.entries.push(Entry {
+                key,
+                value,
+                file: self.file.to_string(),
+                line,
+                secret: None,
+            }),
+            None => self.problem(line, format!("{}: expected a string, integer or boolean", key)),
+        }
+    }
+
+    // A [[cross_connect]] or [template.NAME] table, as prefix.param entries
+    fn push_table(&mut self, prefix: &str, table: &dyn TableLike) {
+        for (key, item) in table.iter() {
+            let Some(section) = item.as_table_like() else {
+                match TOML_KEYS.iter().find(|(s, k, _)| s.is_empty() && *k == key) {
+                    Some((_, _, flat)) => self.push(format!("{}.{}", prefix, flat), item),
+                    None => self.problem(line_of(self.contents, item), toml_unknown(key)),
+                }
+                continue;
+            };
+            for (name, item) in section.iter() {
+                match TOML_KEYS.iter().find(|(s, k, _)| *s == key && *k == name) {
+                    Some((_, _, flat)) => self.push(format!("{}.{}", prefix, flat), item),
+                    None => self.problem(line_of(self.contents, item),
+                        toml_unknown(&format!("{}.{}", key, name))),
+                }
+            }
+        }
+    }
+}
+
+// [[cross_connect]] tables are numbered from `next_slot` on, in file order
+fn parse_toml(file: &str, contents: &str, next_slot: &mut usize, diagnostics: &mut Vec<Diagnostic>) -> Vec<Entry> {
+    let document = match Document::parse(contents) {
+        Ok(document) => document,
+        Err(e) => {
+            let line = e.span().map(|span| line_at(contents, span.start)).unwrap_or(0);
+            diagnostics.push(Diagnostic { file: file.to_string(), line, message: e.message().to_string() });
+            return Vec::new();
+        }
+    };
+
+    let mut reader = TomlReader { file, contents, entries: Vec::new(), diagnostics };
+    for (key, item) in document.iter() {
+        match key {
+            "cross_connect" => {
+                let Some(tables) = item.as_array_of_tables() else {
+                    reader.problem(line_of(contents, item),
+                        "cross_connect must be written as [[cross_connect]] tables".to_string());
+                    continue;
+                };
+                for table in tables.iter() {
+                    reader.push_table(&format!("cross_connect{:04}", *next_slot), table);
+                    *next_slot += 1;
+                }
+            }
+            "template" => {
+                let Some(templates) = item.as_table_like() else {
+                    reader.problem(line_of(contents, item),
+                        "template must be written as [template.NAME] tables".to_string());
+                    continue;
+                };
+                for (name, template) in templates.iter() {
+                    match template.as_table_like() {
+                        Some(table) => reader.push_table(&format!("template.{}", name), table),
+                        None => reader.problem(line_of(contents, template), format!(
+                            "template.{} must be written as a [template.{}] table", name, name)),
+                    }
+                }
+            }
+            // Several files can be included as an array of paths
+            "include" if item.is_array() => {
+                for value in item.as_array().into_iter().flatten() {
+                    let line = value.span().map(|span| line_at(contents, span.start)).unwrap_or(0);
+                    match value.as_str() {
+                        Some(path) => reader.entries.push(Entry {
+                            key: key.to_string(),
+                            value: path.to_string(),
+                            file: file.to_string(),
+                            line,
+                            secret: None,
+                        }),
+                        None => reader.problem(line, "include: expected a path".to_string()),
+                    }
+                }
+            }
+            _ => reader.push(key.to_string(), item),
+        }
+    }
+    reader.entries
+}
+
+// Convert a configuration file to the structured (TOML) format. The file
+// must pass validation first. Only settings present in the file are
+// written, and each cross-connect keeps its name so log output is unchanged.
+// Included files are merged into the result; templates are kept.
+pub fn convert_to_toml(path: &str) -> Result<String, ConfigError> {
+    Config::from_file(path)?;
+    let mut diagnostics = Vec::new();
+    let entries = read_file(path, &mut diagnostics)?;
+    Ok(to_toml(&entries))
+}
+
+// Rewrite a legacy (single bridge) file in the cross-connect format: each
+// top-level bridge setting becomes cross_connect0000.param on the same line,
+// so comments and layout are kept. Keys the legacy format ignored are
+// commented out rather than switched on, and returned as warnings. The file
+// must pass validation as it would after migration.
+pub fn migrate_legacy(path: &str) -> Result<(String, Vec<Diagnostic>), ConfigError> {
+    let mut diagnostics = Vec::new();
+    let mut entries = read_file(path, &mut diagnostics)?;
+    if entries.iter().any(|e| split_cross_connect_key(&e.key).is_some()) {
+        diagnostics.push(Diagnostic {
+            file: path.to_string(),
+            line: 0,
+            message: "already uses cross_connect entries, nothing to migrate".to_string(),
+        });
+        return Err(diagnostics.into());
+    }
+
+    let mut warnings = Vec::new();
+    let mut renamed: HashSet<usize> = HashSet::new();  // Lines of the main file moved into the bridge
+    let mut ignored: HashSet<usize> = HashSet::new();
+    for entry in entries.iter().filter(|e| e.file == path) {
+        if kind_of(GLOBAL_KEYS, &entry.key).is_some() || entry.key == "include" {
+            continue;
+        }
+        if LEGACY_KEYS.contains(&entry.key.as_str()) {
+            renamed.insert(entry.line);
+            continue;
+        }
+        let message = if kind_of(CROSS_CONNECT_KEYS, &entry.key).is_some() {
+            format!("{} was ignored by the legacy format; commented out as cross_connect0000.{}",
+                entry.key, entry.key)
+        } else {
+            format!("unknown key '{}' was ignored; commented out", entry.key)
+        };
+        warnings.push(Diagnostic::at(entry, message));
+        ignored.insert(entry.line);
+    }
+
+    // Validate what the migrated file will hold. Values are copied as
+    // written, so ${VAR} and @file: references are not expanded here and
+    // their values are not checked: they may only be set where it runs.
+    entries.retain(|e| e.file != path || !ignored.contains(&e.line));
+    for entry in entries.iter_mut().filter(|e| e.file == path && renamed.contains(&e.line)) {
+        entry.key = format!("cross_connect0000.{}", entry.key);
+    }
+    let references: HashSet<(String, usize)> = entries.iter()
+        .filter(|e| e.value.contains("${") || e.value.starts_with("@file:"))
+        .map(|e| (e.file.clone(), e.line))
+        .collect();
+    diagnostics.extend(check_entries(&mut entries).into_iter()
+        .filter(|d| !references.contains(&(d.file.clone(), d.line))));
+    if !diagnostics.is_empty() {
+        return Err(diagnostics.into());
+    }
+
+    let contents = fs::read_to_string(path).map_err(|e| ConfigError::from(vec![Diagnostic {
+        file: path.to_string(),
+        line: 0,
+        message: format!("Failed to read config file: {}", e),
+    }]))?;
+    let mut out = String::from("# Migrated from the legacy single-bridge format by rax25kb --migrate-config\n");
+    for (index, raw) in contents.lines().enumerate() {
+        let line = index + 1;
+        let indent = &raw[..raw.len() - raw.trim_start().len()];
+        let rest = raw.trim_start();
+        let moved = |rest: &str| rest.split_once('=')
+            .map(|(key, value)| format!("cross_connect0000.{}={}", key.trim(), value));
+        match (renamed.contains(&line), ignored.contains(&line)) {
+            (true, _) => out.push_str(&format!("{}{}", indent, moved(rest).unwrap_or_default())),
+            (_, true) => {
+                let known = rest.split_once('=')
+                    .is_some_and(|(key, _)| kind_of(CROSS_CONNECT_KEYS, key.trim()).is_some());
+                let text = if known { moved(rest).unwrap_or_default() } else { rest.to_string() };
+                out.push_str(&format!("{}# {}", indent, text));
+            }
+            _ => out.push_str(raw),
+        }
+        out.push('\n');
+    }
+    Ok((out, warnings))
+}
+
+fn toml_scalar(kind: Option<Kind>, value: &str) -> String {
+    match kind {
+        Some(Kind::Bool) => matches!(value.to_lowercase().as_str(), "1" | "true" | "yes").to_string(),
+        Some(Kind::Int(..)) => value.to_string(),
+        Some(Kind::Choice(_)) if value.parse::<i64>().is_ok() => value.to_string(),
+        _ => Value::from(value).to_string(),
+    }
+}
+
+// Parameters of a [[cross_connect]] or [template.NAME] table; sections go
+// into [`table`.section] subtables
+fn toml_params(out: &mut String, table: &str, params: &[(&str, &str)]) {
+    let value_of = |flat: &str| params.iter().find(|(p, _)| *p == flat).map(|(_, v)| *v);
+    let mut section = "";
+    for &(sub, key, flat) in TOML_KEYS {
+        let Some(value) = value_of(flat) else { continue };
+        if sub != section {
+            out.push_str(&format!("\n[{}.{}]\n", table, sub));
+            section = sub;
+        }
+        out.push_str(&format!("{} = {}\n", key, toml_scalar(kind_of(CROSS_CONNECT_KEYS, flat), value)));
+    }
+}
+
+fn to_toml(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    let mut slots: Vec<(&str, Vec<(&str, &str)>)> = Vec::new();
+    let mut templates: Vec<(&str, Vec<(&str, &str)>)> = Vec::new();
+    let legacy = !entries.iter().any(|e| split_cross_connect_key(&e.key).is_some());
+    for entry in entries {
+        let (group, table, param) = if let Some((name, param)) = split_template_key(&entry.key) {
+            (&mut templates, name, param)
+        } else {
+            match split_cross_connect_key(&entry.key) {
+                Some((slot, param)) => (&mut slots, slot, param),
+                None if legacy && LEGACY_KEYS.contains(&entry.key.as_str()) => {
+                    (&mut slots, "cross_connect0000", entry.key.as_str())
+                }
+                None => {
+                    let value = toml_scalar(kind_of(GLOBAL_KEYS, &entry.key), &entry.value);
+                    out.push_str(&format!("{} = {}\n", entry.key, value));
+                    continue;
+                }
+            }
+        };
+        match group.iter_mut().find(|(t, _)| *t == table) {
+            Some((_, params)) => params.push((param, &entry.value)),
+            None => group.push((table, vec![(param, &entry.value)])),
+        }
+    }
+    slots.sort_by_key(|(slot, _)| *slot);
+
+    for (name, params) in &templates {
+        let table = format!("template.{}", name);
+        out.push_str(&format!("\n[{}]\n", table));
+        toml_params(&mut out, &table, params);
+    }
+    for (slot, params) in &slots {
+        out.push_str("\n[[cross_connect]]\n");
+        if !params.iter().any(|(p, _)| *p == "name") {
+            out.push_str(&format!("name = {}\n", Value::from(*slot)));
+        }
+        toml_params(&mut out, "cross_connect", params);
+    }
+    out
+}
+
+fn check_value(entry: &Entry, name: &str, kind: Kind) -> Option<Diagnostic> {
+    let value = entry.value.as_str();
+    let shown = if entry.secret.is_some() { "the value read from file".to_string() } else { format!("'{}'", value) };
+    let problem = match kind {
+        _ if value.is_empty() => format!("{}: empty value", name),
+        Kind::Bool if !BOOL_VALUES.contains(&value.to_lowercase().as_str()) => {
+            format!("{}: {} is not a boolean (use yes or no)", name, shown)
+        }
+        Kind::Int(min, max) => match value.parse::<i64>() {
+            Err(_) => format!("{}: {} is not a number", name, shown),
+            Ok(n) if n < min || n > max => {
+                let number = if entry.secret.is_some() { shown } else { n.to_string() };
+                format!("{}: {} is out of range ({}-{})", name, number, min, max)
+            }
+            Ok(_) => return None,
+        },
+        Kind::Choice(choices) if !choices.contains(&value.to_lowercase().as_str()) => {
+            format!("{}: {} is not one of {}", name, shown, choices.join(", "))
+        }
+        _ => return None,
+    };
+    Some(Diagnostic::at(entry, problem))
+}
+
+// Expand ${VAR} and ${VAR:-default} (used when VAR is unset or empty) in
+// every value, then replace a value of @file:PATH with the contents of PATH,
+// without trailing newlines. A relative PATH starts at the directory of the
+// file the entry came from. $$ is a literal $. An unset variable without a
+// default or an unreadable file is an error, never left in as literal text;
+// the entry is dropped after it is reported.
+pub fn interpolate(entries: &mut Vec<Entry>, diagnostics: &mut Vec<Diagnostic>) {
+    entries.retain_mut(|entry| {
+        let result = expand_variables(&entry.value).and_then(|value| {
+            let Some(path) = value.strip_prefix("@file:") else { return Ok(value) };
+            entry.secret = Some(value.clone());
+            let path = Path::new(&entry.file).parent().unwrap_or(Path::new("")).join(path);
+            fs::read_to_string(&path)
+                .map(|contents| contents.trim_end_matches(['\r', '\n']).to_string())
+                .map_err(|e| format!("cannot read {}: {}", path.display(), e))
+        });
+        match result {
+            Ok(value) => {
+                entry.value = value;
+                true
+            }
+            Err(message) => {
+                diagnostics.push(Diagnostic::at(entry, format!("{}: {}", entry.key, message)));
+                false
+            }
+        }
+    });
+}
+
+fn expand_variables(value: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        if let Some(after) = after.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        let Some(body) = after.strip_prefix('{') else {
+            out.push('$');
+            rest = after;
+            continue;
+        };
+        let end = body.find('}').ok_or("unterminated ${")?;
+        let (name, default) = match body[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&body[..end], None),
+        };
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            return Err(format!("invalid variable name '{}' in ${{{}}}", name, &body[..end]));
+        }
+        match (std::env::var(name), default) {
+            (Ok(set), Some(default)) if set.is_empty() => out.push_str(default),
+            (Ok(set), _) => out.push_str(&set),
+            (Err(_), Some(default)) => out.push_str(default),
+            (Err(_), None) => return Err(format!("environment variable {} is not set", name)),
+        }
+        rest = &body[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+// Command-line options that set a configuration key: (options, key, value).
+// A value of None means the option takes the value as its next argument.
+const CLI_OPTIONS: &[(&[&str], &str, Option<&str>)] = &[
+    (&["-D", "--device"], "serial_port", None),
+    (&["-b", "--baud-rate"], "baud_rate", None),
+    (&["-s", "--stop-bits"], "stop_bits", None),
+    (&["-Q", "--parity"], "parity", None),
+    (&["-x", "--xon-xoff"], "flow_control", Some("software")),
+    (&["-H", "--rts-cts"], "flow_control", Some("hardware")),
+    (&["--dtr-dsr"], "flow_control", Some("dtrdsr")),
+    (&["-N", "--none"], "flow_control", Some("none")),
+    (&["-I", "--address"], "tcp_address", None),
+    (&["-p", "--port"], "tcp_port", None),
+    (&["-d", "--dump"], "dump", Some("yes")),
+    (&["-k", "--kiss"], "parse_kiss", Some("yes")),
+    (&["-a", "--ax25"], "dump_ax25", Some("yes")),
+    (&["-n", "--phil"], "phil_flag", Some("yes")),
+    (&["-R", "--raw-copy"], "raw_copy", Some("yes")),
+    (&["-l", "--logfile"], "logfile", None),
+    (&["-L", "--log-level"], "log_level", None),
+    (&["--console-only"], "log_to_console", Some("yes")),
+    (&["--console-only"], "log_to_file_only", Some("no")),
+    (&["--no-console"], "log_to_console", Some("no")),
+    (&["--no-console"], "log_to_file_only", Some("yes")),
+    (&["-P", "--pidfile"], "pidfile", None),
+    (&["--pcap"], "pcap_file", None),
+    (&["-q", "--quiet"], "quiet_startup", Some("yes")),
+];
+
+// Options main() acts on itself. -c always takes the next argument; the file
+// after a converting option is optional, so another option there is not it.
+const MODE_OPTIONS: &[&str] = &["-h", "--help", "--check-config", "--print-config", "--list-ports"];
+const CONVERT_OPTIONS: &[&str] = &["--convert-config", "--migrate-config"];
+
+// Apply command-line settings on top of the file's entries, in the order
+// given: `--set key=value` for any key, and the short options above, which
+// set the first cross-connect (lowest cross_connectNNNN). A setting replaces
+// the file's value for the same key instead of counting as a duplicate.
+// Anything else that is not an option main() knows is reported.
+pub fn apply_overrides(entries: &mut Vec<Entry>, args: &[String], diagnostics: &mut Vec<Diagnostic>) {
+    let first_slot = entries.iter()
+        .filter_map(|e| split_cross_connect_key(&e.key).map(|(slot, _)| slot.to_string()))
+        .min();
+    let qualify = |key: &str| match &first_slot {
+        Some(slot) if kind_of(GLOBAL_KEYS, key).is_none() => format!("{}.{}", slot, key),
+        _ => key.to_string(),
+    };
+
+    let mut overrides = Vec::new();
+    let mut i = 1;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        i += 1;
+        if arg == "--set" {
+            let Some(setting) = args.get(i) else {
+                diagnostics.push(command_line("--set needs key=value".to_string()));
+                break;
+            };
+            i += 1;
+            match setting.split_once('=') {
+                Some((key, value)) => overrides.push((key.trim().to_string(), value.trim().to_string())),
+                None => diagnostics.push(command_line(format!("--set {}: expected key=value", setting))),
+            }
+            continue;
+        }
+        if arg == "-c" {
+            i += 1;
+            continue;
+        }
+        if MODE_OPTIONS.contains(&arg) {
+            continue;
+        }
+        if CONVERT_OPTIONS.contains(&arg) {
+            if args.get(i).is_some_and(|next| !next.starts_with('-')) {
+                i += 1;
+            }
+            continue;
+        }
+        let mut known = false;
+        let mut takes_value = false;
+        for &(options, key, value) in CLI_OPTIONS.iter().filter(|(options, _, _)| options.contains(&arg)) {
+            known = true;
+            match value {
+                Some(value) => overrides.push((qualify(key), value.to_string())),
+                None => {
+                    takes_value = true;
+                    match args.get(i) {
+                        Some(value) => overrides.push((qualify(key), value.clone())),
+                        None => diagnostics.push(command_line(format!("{} needs a value", options[0]))),
+                    }
+                }
+            }
+        }
+        if !known {
+            diagnostics.push(command_line(format!("unknown option '{}'", arg)));
+        }
+        if takes_value {
+            i += 1;
+        }
+    }
+
+    for (key, value) in overrides {
+        entries.retain(|e| e.key != key);
+        entries.push(Entry { key, value, file: "command line".to_string(), line: 0, secret: None });
+    }
+}
+
+fn command_line(message: String) -> Diagnostic {
+    Diagnostic { file: "command line".to_string(), line: 0, message }
+}
+
+// First pass: every entry on its own. Templates are then applied, so the
+// parameters a cross-connect inherits count as set by it.
+pub fn check_entries(entries: &mut Vec<Entry>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut seen: HashMap<&str, &Entry> = HashMap::new();
+    let legacy = !entries.iter().any(|e| e.key.starts_with("cross_connect"));
+
+    let mut rejected: HashSet<usize> = HashSet::new();   // Entries reported below
+
+    for (index, entry) in entries.iter().enumerate() {
+        if let Some(first) = seen.insert(&entry.key, entry) {
+            diagnostics.push(Diagnostic::at(entry, format!(
+                "{} is already set at {}:{}", entry.key, first.file, first.line)));
+            rejected.insert(index);
+        }
+
+        let kind = if let Some((_, param)) = split_cross_connect_key(&entry.key) {
+            match kind_of(CROSS_CONNECT_KEYS, param) {
+                Some(kind) => kind,
+                None if kind_of(GLOBAL_KEYS, param).is_some() => {
+                    diagnostics.push(Diagnostic::at(entry, format!(
+                        "{} is a global setting, not a cross-connect parameter", param)));
+                    rejected.insert(index);
+                    continue;
+                }
+                None => {
+                    diagnostics.push(Diagnostic::at(entry, format!(
+                        "unknown cross-connect parameter '{}'", param)));
+                    rejected.insert(index);
+                    continue;
+                }
+            }
+        } else if entry.key.starts_with("cross_connect") {
+            diagnostics.push(Diagnostic::at(entry, format!(
+                "malformed key '{}' (expected cross_connectNNNN.parameter)", entry.key)));
+            rejected.insert(index);
+            continue;
+        } else if entry.key.starts_with("template.") {
+            match split_template_key(&entry.key) {
+                Some((_, "name")) => {
+                    diagnostics.push(Diagnostic::at(entry,
+                        "name cannot be set by a template".to_string()));
+                    rejected.insert(index);
+                    continue;
+                }
+                Some((_, param)) => match kind_of(CROSS_CONNECT_KEYS, param) {
+                    Some(kind) => kind,
+                    None => {
+                        diagnostics.push(Diagnostic::at(entry, format!(
+                            "unknown cross-connect parameter '{}'", param)));
+                        rejected.insert(index);
+                        continue;
+                    }
+                },
+                None => {
+                    diagnostics.push(Diagnostic::at(entry, format!(
+                        "malformed key '{}' (expected template.NAME.parameter)", entry.key)));
+                    rejected.insert(index);
+                    continue;
+                }
+            }
+        } else if let Some(kind) = kind_of(GLOBAL_KEYS, &entry.key) {
+            kind
+        } else if LEGACY_KEYS.contains(&entry.key.as_str()) {
+            if !legacy {
+                diagnostics.push(Diagnostic::at(entry, format!(
+                    "{} is ignored when cross_connect entries are present (use cross_connectNNNN.{})",
+                    entry.key, entry.key)));
+                rejected.insert(index);
+                continue;
+            }
+            kind_of(CROSS_CONNECT_KEYS, &entry.key).unwrap_or(Kind::Text)
+        } else if kind_of(CROSS_CONNECT_KEYS, &entry.key).is_some() {
+            diagnostics.push(Diagnostic::at(entry, format!(
+                "{} is only valid as cross_connectNNNN.{}", entry.key, entry.key)));
+            rejected.insert(index);
+            continue;
+        } else {
+            diagnostics.push(Diagnostic::at(entry, format!("unknown key '{}'", entry.key)));
+            rejected.insert(index);
+            continue;
+        };
+
+        if let Some(diagnostic) = check_value(entry, &entry.key, kind) {
+            diagnostics.push(diagnostic);
+            rejected.insert(index);
+        }
+    }
+
+    // Set aside what was reported, so that check_config() can still look at
+    // the rest
+    let mut dropped = Vec::new();
+    for index in (0..entries.len()).rev().filter(|index| rejected.contains(index)) {
+        dropped.insert(0, entries.remove(index));
+    }
+
+    apply_templates(entries, &mut diagnostics);
+
+    // Every bridge needs a serial port. Those without one are dropped too.
+    let mut ids: Vec<&str> = Vec::new();
+    let mut with_serial: HashSet<&str> = HashSet::new();
+    for entry in entries.iter().chain(&dropped) {
+        if let Some((id, param)) = split_cross_connect_key(&entry.key) {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+            if param == "serial_port" {
+                with_serial.insert(id);
+            }
+        }
+    }
+    let mut without_serial: Vec<String> = Vec::new();
+    for id in ids.into_iter().filter(|id| !with_serial.contains(id)) {
+        let first = entries.iter().chain(&dropped)
+            .find(|e| split_cross_connect_key(&e.key).map(|(i, _)| i) == Some(id))
+            .expect("id taken from entries");
+        diagnostics.push(Diagnostic::at(first, format!("{} has no serial_port", id)));
+        without_serial.push(id.to_string());
+    }
+    entries.retain(|e| !split_cross_connect_key(&e.key)
+        .is_some_and(|(id, _)| without_serial.iter().any(|missing| missing == id)));
+    let first = entries.first().or(dropped.first());
+    let has_serial = entries.iter().chain(&dropped).any(|e| e.key == "serial_port");
+    if let Some(first) = first.filter(|_| legacy && !has_serial) {
+        diagnostics.push(Diagnostic {
+            file: first.file.clone(),
+            line: 0,
+            message: "no serial_port and no cross_connect entries".to_string(),
+        });
+    }
+
+    diagnostics
+}
+
+// Give each cross-connect that names a template the parameters it does not
+// set itself: first from that template, then from the template it inherits
+// from, and so on. Inherited entries keep the template's file and line.
+fn apply_templates(entries: &mut Vec<Entry>, diagnostics: &mut Vec<Diagnostic>) {
+    let defines = |name: &str| entries.iter()
+        .any(|e| split_template_key(&e.key).is_some_and(|(n, _)| n == name));
+    let mut inherited: Vec<Entry> = Vec::new();
+
+    for entry in entries.iter() {
+        let Some((slot, "template")) = split_cross_connect_key(&entry.key) else { continue };
+        let mut chain: Vec<&str> = Vec::new();
+        let mut next = Some(entry);
+        while let Some(from) = next {
+            let name = from.value.as_str();
+            let problem = if chain.contains(&name) {
+                Some(format!("template {} inherits from itself ({} -> {})", name, chain.join(" -> "), name))
+            } else if !defines(name) {
+                Some(format!("unknown template '{}'", name))
+            } else {
+                None
+            };
+            if let Some(message) = problem {
+                let diagnostic = Diagnostic::at(from, message);
+                if !diagnostics.contains(&diagnostic) {
+                    diagnostics.push(diagnostic);
+                }
+                break;
+            }
+            chain.push(name);
+            next = entries.iter().find(|e| split_template_key(&e.key) == Some((name, "template")));
+        }
+
+        for name in chain {
+            for source in entries.iter() {
+                let Some((template, param)) = split_template_key(&source.key) else { continue };
+                if template != name || param == "template" {
+                    continue;
+                }
+                let key = format!("{}.{}", slot, param);
+                if entries.iter().chain(inherited.iter()).any(|e| e.key == key) {
+                    continue;
+                }
+                inherited.push(Entry { key, ..source.clone() });
+            }
+        }
+    }
+    entries.extend(inherited);
+}
+
+// Where to point a diagnostic about `param` of cross-connect `id`: the entry
+// that set it, or else the cross-connect's first entry
+fn locate<'a>(entries: &'a [Entry], id: &str, param: &str) -> Option<&'a Entry> {
+    let legacy = !entries.iter().any(|e| split_cross_connect_key(&e.key).is_some());
+    let sets_param = |e: &&Entry| if legacy {
+        e.key == param
+    } else {
+        split_cross_connect_key(&e.key) == Some((id, param))
+    };
+    let belongs = |e: &&Entry| split_cross_connect_key(&e.key).map(|(i, _)| i) == Some(id);
+    entries.iter().find(sets_param)
+        .or_else(|| entries.iter().find(belongs))
+        .or_else(|| entries.first())
+}
+
+fn report(entry: Option<&Entry>, message: String) -> Diagnostic {
+    match entry {
+        Some(entry) => Diagnostic::at(entry, message),
+        None => Diagnostic { file: String::new(), line: 0, message },
+    }
+}
+
+fn is_wildcard(address: &str) -> bool {
+    matches!(address, "0.0.0.0" | "::" | "[::]")
+}
+
+// Two listeners on `port` collide unless both name different specific addresses
+fn binds_overlap(a: &str, b: &str) -> bool {
+    a == b || is_wildcard(a) || is_wildcard(b)
+}
+
+fn listens(cc: &CrossConnect) -> bool {
+    cc.serial_to_serial.is_none() && cc.tcp_mode == TcpMode::Server
+}
+
+// Second pass: the configuration as a whole
+pub fn check_config(config: &Config, entries: &[Entry]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut ccs: Vec<&CrossConnect> = config.cross_connects.iter().collect();
+    ccs.sort_by(|a, b| a.slot.cmp(&b.slot));
+
+    // Device selectors (usb:VID:PID, by-id:NAME) are resolved at open time,
+    // but their syntax can be checked now
+    for cc in &ccs {
+        let devices = [("serial_port", Some(&cc.serial_port)), ("serial_to_serial", cc.serial_to_serial.as_ref())];
+        for (param, device) in devices {
+            if let Some(Err(e)) = device.map(|device| crate::ports::parse(device)) {
+                diagnostics.push(report(locate(entries, &cc.slot, param), format!("{} {}", cc.id, e)));
+            }
+        }
+    }
+
+    // TNC command scripts
+    for cc in &ccs {
+        let scripts = [("init_script", cc.init_script.as_ref()), ("exit_script", cc.exit_script.as_ref())];
+        for (param, script) in scripts {
+            if let Some(Err(e)) = script.map(|script| crate::script::parse(script)) {
+                diagnostics.push(report(locate(entries, &cc.slot, param), format!("{} {}: {}", cc.id, param, e)));
+            }
+        }
+    }
+    // The device is opened with the settings of its primary cross-connect, so
+    // a secondary's own scripts would never run (the same ones, say from a
+    // shared template, are fine)
+    for cc in ccs.iter().filter(|cc| !cc.is_primary_port) {
+        let Some(primary) = ccs.iter().find(|other| other.is_primary_port && other.serial_port == cc.serial_port)
+            else { continue };
+        let scripts = [
+            ("init_script", &cc.init_script, &primary.init_script),
+            ("exit_script", &cc.exit_script, &primary.exit_script),
+        ];
+        for (param, _, _) in scripts.into_iter().filter(|(_, script, used)| script.is_some() && script != used) {
+            diagnostics.push(report(locate(entries, &cc.slot, param), format!(
+                "{} sets {} but does not open {}; set it on {}, which does",
+                cc.id, param, cc.serial_port, primary.id)));
+        }
+    }
+
+    // KISS parameters are KISS frames, which raw_copy does not send
+    for cc in ccs.iter().filter(|cc| cc.raw_copy) {
+        let keys = ["txdelay", "persist", "slottime", "txtail", "fullduplex"];
+        let Some(key) = keys.into_iter().find(|key| cross_connect_value(cc, key).is_some()) else { continue };
+        diagnostics.push(report(locate(entries, &cc.slot, key), format!(
+            "{} sets {} but has raw_copy=yes, which sends no KISS commands", cc.id, key)));
+    }
+
+    // KISS command firewall; it needs KISS framing to find the commands
+    for cc in &ccs {
+        let filters = [
+            ("kiss_command_filter", crate::firewall::Filter::parse(cc.kiss_command_filter.as_deref(), None)),
+            ("kiss_client_filter", crate::firewall::Filter::parse(None, cc.kiss_client_filter.as_deref())),
+        ];
+        for (param, filter) in filters {
+            let problem = match filter {
+                Err(e) => Some(format!("{} {}: {}", cc.id, param, e)),
+                Ok(filter) if cc.raw_copy && !filter.is_empty() => Some(format!(
+                    "{} sets {} but has raw_copy=yes, which passes commands unframed", cc.id, param)),
+                Ok(_) => None,
+            };
+            if let Some(message) = problem {
+                diagnostics.push(report(locate(entries, &cc.slot, param), message));
+            }
+        }
+    }
+
+    // Names appear in every log line and must tell bridges apart
+    for (i, cc) in ccs.iter().enumerate() {
+        if let Some(other) = ccs[..i].iter().find(|other| other.id == cc.id) {
+            diagnostics.push(report(locate(entries, &cc.slot, "name"), format!(
+                "name '{}' is already used by {}", cc.id, other.slot)));
+        }
+    }
+
+    // TCP listeners
+    for (i, cc) in ccs.iter().enumerate().filter(|(_, cc)| listens(cc)) {
+        if let Some(other) = ccs[..i].iter().find(|other| listens(other)
+            && other.tcp_port == cc.tcp_port
+            && binds_overlap(&other.tcp_address, &cc.tcp_address)) {
+            diagnostics.push(report(locate(entries, &cc.slot, "tcp_port"), format!(
+                "{} listens on {}:{}, already used by {} ({}:{})", cc.id, cc.tcp_address,
+                cc.tcp_port, other.id, other.tcp_address, other.tcp_port)));
+        }
+    }
+
+    // Client mode needs somewhere to connect to
+    for cc in ccs.iter().filter(|cc| cc.serial_to_serial.is_none() && cc.tcp_mode == TcpMode::Client) {
+        if cc.tcp_server_address.is_none() || cc.tcp_server_port.is_none() {
+            diagnostics.push(report(locate(entries, &cc.slot, "tcp_mode"), format!(
+                "{} has tcp_mode=client but no tcp_server_address and tcp_server_port", cc.id)));
+        }
+    }
+
+    // AGW: every cross-connect with agw_enable binds agw_server_port
+    if config.agw_server_enable {
+        for cc in ccs.iter().filter(|cc| listens(cc) && cc.tcp_port == config.agw_server_port
+            && binds_overlap(&cc.tcp_address, &config.agw_server_address)) {
+            diagnostics.push(report(locate(entries, &cc.slot, "tcp_port"), format!(
+                "{} listens on {}:{}, which is the AGW server port", cc.id, cc.tcp_address, cc.tcp_port)));
+        }
+        let agw: Vec<&&CrossConnect> = ccs.iter().filter(|cc| cc.agw_enable).collect();
+        if agw.len() > 1 {
+            let ids: Vec<&str> = agw.iter().map(|cc| cc.id.as_str()).collect();
+            diagnostics.push(report(entries.iter().find(|e| e.key == "agw_server_enable"), format!(
+                "agw_enable is set on {} cross-connects ({}), but each binds agw_server_port {}",
+                agw.len(), ids.join(", "), config.agw_server_port)));
+        }
+        for (i, cc) in agw.iter().enumerate() {
+            if let Some(other) = agw[..i].iter().find(|other| other.agw_port == cc.agw_port) {
+                diagnostics.push(report(locate(entries, &cc.slot, "agw_port"), format!(
+                    "{} uses agw_port {}, already used by {}", cc.id, cc.agw_port, other.id)));
+            }
+        }
+    }
+
+    // Cross-connects sharing a serial device: one primary (kiss_port=0) that
+    // owns the line settings, and no two routes for the same port on the line.
+    // A cross-connect alone on its device is its primary whatever its port.
+    let mut devices: Vec<(&str, Vec<&CrossConnect>)> = Vec::new();
+    for cc in &ccs {
+        match devices.iter_mut().find(|(device, _)| *device == cc.serial_port) {
+            Some((_, group)) => group.push(cc),
+            None => devices.push((&cc.serial_port, vec![cc])),
+        }
+    }
+    for (device, group) in &devices {
+        if group.len() > 1 && !group.iter().any(|cc| cc.kiss_port == 0) {
+            for cc in group {
+                diagnostics.push(report(locate(entries, &cc.slot, "kiss_port"), format!(
+                    "{} uses kiss_port {} on {}, but no cross-connect there has kiss_port 0 \
+                     to own the serial settings", cc.id, cc.kiss_port, device)));
+            }
+        }
+        for (i, cc) in group.iter().enumerate() {
+            if let Some(other) = group[..i].iter().find(|other| other.wire_port() == cc.wire_port()) {
+                let param = if cc.xkiss_mode { "xkiss_port" } else { "kiss_port" };
+                diagnostics.push(report(locate(entries, &cc.slot, param), format!(
+                    "{} uses port {} on {}, already used by {}", cc.id, cc.wire_port(), device, other.id)));
+            }
+        }
+    }
+
+    // Pty endpoints: one link path each, and not on a serial-to-serial bridge,
+    // which has no host side
+    for (i, cc) in ccs.iter().enumerate() {
+        let Some(ref link) = cc.pty_link else { continue };
+        let problem = if !cfg!(unix) {
+            Some("pty endpoints are only supported on Unix".to_string())
+        } else if cc.serial_to_serial.is_some() {
+            Some(format!("{} has both pty_link and serial_to_serial", cc.id))
+        } else {
+            ccs[..i].iter().find(|other| other.pty_link.as_ref() == Some(link))
+                .map(|other| format!("{} links {}, already used by {}", cc.id, link, other.id))
+        };
+        if let Some(message) = problem {
+            diagnostics.push(report(locate(entries, &cc.slot, "pty_link"), message));
+        }
+    }
+
+    for cc in ccs.iter().filter(|cc| cc.serial_to_serial.as_deref() == Some(cc.serial_port.as_str())) {
+        diagnostics.push(report(locate(entries, &cc.slot, "serial_to_serial"), format!(
+            "{} connects {} to itself", cc.id, cc.serial_port)));
+    }
+
+    diagnostics
+}
+
+// Value as the flat format must spell it to read it back unchanged
+fn flat_value(value: &str) -> String {
+    if value.is_empty() || value.contains('#') || value.trim() != value {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn global_value(config: &Config, key: &str) -> Option<String> {
+    match key {
+        "log_level" => Some(config.log_level.to_string()),
+        "logfile" => config.logfile.clone(),
+        "pidfile" => config.pidfile.clone(),
+        "log_to_console" => Some(yes_no(config.log_to_console)),
+        "log_to_file_only" => Some(yes_no(config.log_to_file_only)),
+        "quiet_startup" => Some(yes_no(config.quiet_startup)),
+        "pcap_file" => config.pcap_file.clone(),
+        "max_tcp_clients" => Some(config.max_tcp_clients.to_string()),
+        "agw_server_enable" => Some(yes_no(config.agw_server_enable)),
+        "agw_server_address" => Some(config.agw_server_address.clone()),
+        "agw_server_port" => Some(config.agw_server_port.to_string()),
+        "agw_max_clients" => Some(config.agw_max_clients.to_string()),
+        _ => None,
+    }
+}
+
+fn cross_connect_value(cc: &CrossConnect, key: &str) -> Option<String> {
+    match key {
+        "name" => (cc.id != cc.slot).then(|| cc.id.clone()),
+        "serial_port" => Some(cc.serial_port.clone()),
+        "baud_rate" => Some(cc.baud_rate.to_string()),
+        "flow_control" => Some(match cc.flow_control {
+            FlowControl::None => "none",
+            FlowControl::Software => "software",
+            FlowControl::Hardware => "hardware",
+            FlowControl::DtrDsr => "dtrdsr",
+        }.to_string()),
+        "stop_bits" => Some(match cc.stop_bits {
+            StopBits::One => "1",
+            StopBits::Two => "2",
+        }.to_string()),
+        "data_bits" => Some(match cc.data_bits {
+            DataBits::Seven => "7",
+            DataBits::Eight => "8",
+        }.to_string()),
+        "parity" => Some(match cc.parity {
+            Parity::None => "none",
+            Parity::Odd => "odd",
+            Parity::Even => "even",
+        }.to_string()),
+        // Line breaks in a script are ignored; the flat format cannot hold them
+        "init_script" => cc.init_script.as_ref().map(|script| script.replace(['\r', '\n'], "")),
+        "exit_script" => cc.exit_script.as_ref().map(|script| script.replace(['\r', '\n'], "")),
+        "tcp_address" => Some(cc.tcp_address.clone()),
+        "tcp_port" => Some(cc.tcp_port.to_string()),
+        "tcp_mode" => Some(match cc.tcp_mode {
+            TcpMode::Server => "server",
+            TcpMode::Client => "client",
+            TcpMode::None => "none",
+        }.to_string()),
+        "tcp_server_address" => cc.tcp_server_address.clone(),
+        "tcp_server_port" => cc.tcp_server_port.map(|port| port.to_string()),
+        "pty_link" => cc.pty_link.clone(),
+        "kiss_port" => Some(cc.kiss_port.to_string()),
+        "kiss_chan" => Some(cc.kiss_chan.to_string()),
+        "kiss_copy" => Some(yes_no(cc.kiss_copy)),
+        "xkiss_mode" => Some(yes_no(cc.xkiss_mode)),
+        "xkiss_port" => cc.xkiss_port.map(|port| port.to_string()),
+        "xkiss_checksum" => Some(yes_no(cc.xkiss_checksum)),
+        "xkiss_polling" => Some(yes_no(cc.xkiss_polling)),
+        "xkiss_poll_timer_ms" => Some(cc.xkiss_poll_timer_ms.to_string()),
+        "xkiss_rx_buffer_size" => Some(cc.xkiss_rx_buffer_size.to_string()),
+        "xkiss_emulation" => Some(yes_no(cc.xkiss_emulation)),
+        "ackmode_emulation" => Some(yes_no(cc.ackmode_emulation)),
+        "txdelay" => cc.txdelay.map(|value| value.to_string()),
+        "persist" => cc.persist.map(|value| value.to_string()),
+        "slottime" => cc.slottime.map(|value| value.to_string()),
+        "txtail" => cc.txtail.map(|value| value.to_string()),
+        "fullduplex" => cc.fullduplex.map(yes_no),
+        "kiss_param_interval" => Some(cc.kiss_param_interval.to_string()),
+        "kiss_param_policy" => Some(match cc.kiss_param_policy {
+            ParamPolicy::Pass => "pass",
+            ParamPolicy::Override => "override",
+            ParamPolicy::Block => "block",
+        }.to_string()),
+        "kiss_command_filter" => cc.kiss_command_filter.clone(),
+        "kiss_client_filter" => cc.kiss_client_filter.clone(),
+        "serial_to_serial" => cc.serial_to_serial.clone(),
+        "tcp_to_tcp_dangerous" => Some(yes_no(cc.tcp_to_tcp_dangerous)),
+        "tcp_to_tcp_also_dangerous" => Some(yes_no(cc.tcp_to_tcp_also_dangerous)),
+        "phil_flag" => Some(yes_no(cc.phil_flag)),
+        "dump" => Some(yes_no(cc.dump_frames)),
+        "parse_kiss" => Some(yes_no(cc.parse_kiss)),
+        "dump_ax25" => Some(yes_no(cc.dump_ax25)),
+        "raw_copy" => Some(yes_no(cc.raw_copy)),
+        "reframe_large_packets" => Some(yes_no(cc.reframe_large_packets)),
+        "max_frame_len" => Some(cc.max_frame_len.to_string()),
+        "agw_port" => Some(cc.agw_port.to_string()),
+        "agw_enable" => Some(yes_no(cc.agw_enable)),
+        _ => None,
+    }
+}
+
+// The configuration in effect, in the flat format: every setting with the
+// value used (defaults included) after includes, templates and command-line
+// settings have been applied. Computed fields are shown as comments, so the
+// output can itself be used as a configuration file.
+pub fn print_config(config: &Config) -> String {
+    // Values read with @file: are shown as the reference, not the secret
+    let shown = |keys: &[&str], value: String| keys.iter()
+        .find_map(|key| config.secrets.get(*key).cloned())
+        .unwrap_or_else(|| flat_value(&value));
+
+    let mut out = String::from("# Effective rax25kb configuration\n");
+    for &(key, _) in GLOBAL_KEYS {
+        if let Some(value) = global_value(config, key) {
+            out.push_str(&format!("{}={}\n", key, shown(&[key], value)));
+        }
+    }
+
+    let mut ccs: Vec<&CrossConnect> = config.cross_connects.iter().collect();
+    ccs.sort_by(|a, b| a.slot.cmp(&b.slot));
+    for cc in ccs {
+        out.push_str(&format!("\n# {}: is_primary_port={} wire_port={} (computed)\n",
+            cc.slot, yes_no(cc.is_primary_port), cc.wire_port()));
+        for &(key, _) in CROSS_CONNECT_KEYS {
+            if let Some(value) = cross_connect_value(cc, key) {
+                // A legacy file sets the bridge's parameters without the prefix
+                let qualified = format!("{}.{}", cc.slot, key);
+                out.push_str(&format!("{}={}\n", qualified, shown(&[&qualified, key], value)));
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Settings = Vec<(String, String)>;
+
+    // Sorted (key, value) pairs, to compare entries read from different formats
+    fn settings(entries: &[Entry]) -> Settings {
+        let mut settings: Settings = entries.iter().map(|e| (e.key.clone(), e.value.clone())).collect();
+        settings.sort();
+        settings
+    }
+
+    // Convert a key=value file to TOML and read it back. Returns the settings
+    // read back, and those of the file with the legacy keys renamed as the
+    // conversion renames them.
+    fn round_trip(contents: &str) -> (Settings, Settings) {
+        let mut diagnostics = Vec::new();
+        let mut entries = parse("test.cfg", contents, &mut diagnostics);
+        let toml = to_toml(&entries);
+        let converted = parse_toml("test.toml", &toml, &mut 0, &mut diagnostics);
+        assert!(diagnostics.is_empty(), "{:?}\n{}", diagnostics, toml);
+
+        let legacy = !entries.iter().any(|e| split_cross_connect_key(&e.key).is_some());
+        for entry in entries.iter_mut().filter(|e| legacy && LEGACY_KEYS.contains(&e.key.as_str())) {
+            entry.key = format!("cross_connect0000.{}", entry.key);
+        }
+        (settings(&converted), settings(&entries))
+    }
+
+    // What the conversion adds: a cross-connect without a name keeps its slot as one
+    fn with_name(mut expected: Settings) -> Settings {
+        expected.push(("cross_connect0000.name".to_string(), "cross_connect0000".to_string()));
+        expected.sort();
+        expected
+    }
+
+    #[test]
+    fn to_toml_round_trips_cross_connects() {
+        // Slots out of order, a template and text that needs quoting
+        let (converted, expected) = round_trip("\
+log_level=5
+logfile=C:\\logs\\rax25kb \"one\".log
+template.radio.baud_rate=9600
+template.radio.flow_control=none
+cross_connect0001.name=vhf
+cross_connect0001.template=radio
+cross_connect0001.serial_port=/dev/ttyUSB1
+cross_connect0001.baud_rate=9600
+cross_connect0001.init_script=KISS ON{cr}\\\"RESTART\\\"{cr}
+cross_connect0000.serial_port=/dev/ttyUSB0
+cross_connect0000.tcp_port=8002
+cross_connect0000.phil_flag=yes
+cross_connect0000.xkiss_mode=no
+cross_connect0000.kiss_command_filter=return:drop, *:allow
+");
+        assert_eq!(converted, with_name(expected));
+    }
+
+    #[test]
+    fn to_toml_moves_legacy_settings_to_cross_connect0000() {
+        let (converted, expected) = round_trip("serial_port=/dev/ttyS0\nbaud_rate=1200\nquiet_startup=no\n");
+        assert_eq!(converted, with_name(expected));
+    }
+
+    fn command(args: &[&str]) -> Vec<String> {
+        ["rax25kb"].iter().chain(args).map(|arg| arg.to_string()).collect()
+    }
+
+    fn overridden(file: &str, args: &[&str]) -> (Vec<Entry>, Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+        let mut entries = parse("test.cfg", file, &mut diagnostics);
+        apply_overrides(&mut entries, &command(args), &mut diagnostics);
+        (entries, diagnostics)
+    }
+
+    fn value<'a>(entries: &'a [Entry], key: &str) -> Option<&'a str> {
+        entries.iter().find(|e| e.key == key).map(|e| e.value.as_str())
+    }
+
+    #[test]
+    fn later_overrides_win() {
+        let file = "cross_connect0000.serial_port=/dev/ttyUSB0\ncross_connect0000.baud_rate=9600\n";
+        let (entries, diagnostics) = overridden(file, &["-b", "1200", "--set", "cross_connect0000.baud_rate=4800"]);
+        assert_eq!(value(&entries, "cross_connect0000.baud_rate"), Some("4800"));
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let (entries, _) = overridden(file, &["--set", "cross_connect0000.baud_rate=4800", "-b", "1200"]);
+        assert_eq!(value(&entries, "cross_connect0000.baud_rate"), Some("1200"));
+    }
+
+    #[test]
+    fn overrides_replace_the_file_value() {
+        let (mut entries, mut diagnostics) = overridden(
+            "cross_connect0000.serial_port=/dev/ttyUSB0\ncross_connect0000.baud_rate=9600\nlog_level=5\n",
+            &["--set", "log_level=7", "--set", "cross_connect0000.baud_rate=19200"]);
+        diagnostics.extend(check_entries(&mut entries));
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let baud_rate: Vec<&Entry> = entries.iter().filter(|e| e.key == "cross_connect0000.baud_rate").collect();
+        assert_eq!(baud_rate.len(), 1);
+        assert_eq!((baud_rate[0].value.as_str(), baud_rate[0].file.as_str()), ("19200", "command line"));
+        assert_eq!(value(&entries, "log_level"), Some("7"));
+    }
+
+    #[test]
+    fn short_options_set_the_lowest_cross_connect() {
+        let (entries, diagnostics) = overridden(
+            "cross_connect0003.serial_port=/dev/ttyUSB3\ncross_connect0001.serial_port=/dev/ttyUSB1\n",
+            &["-b", "1200", "-L", "7", "-x"]);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(value(&entries, "cross_connect0001.baud_rate"), Some("1200"));
+        assert_eq!(value(&entries, "cross_connect0001.flow_control"), Some("software"));
+        assert_eq!(value(&entries, "cross_connect0003.baud_rate"), None);
+        assert_eq!(value(&entries, "log_level"), Some("7"));
+        // A legacy file has no cross-connects, so its top-level keys are set
+        let (entries, _) = overridden("serial_port=/dev/ttyS0\n", &["-b", "1200"]);
+        assert_eq!(value(&entries, "baud_rate"), Some("1200"));
+    }
+
+    #[test]
+    fn unknown_options_are_reported() {
+        let (entries, diagnostics) = overridden("serial_port=/dev/ttyS0\n",
+            &["--sett", "baud_rate=1200", "-b9600", "-c", "x.cfg", "--check-config", "--print-config", "--list-ports", "-d"]);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, ["unknown option '--sett'", "unknown option 'baud_rate=1200'", "unknown option '-b9600'"]);
+        assert_eq!(diagnostics[0].to_string(), "command line: unknown option '--sett'");
+        assert_eq!(value(&entries, "dump"), Some("yes"));
+    }
+
+    #[test]
+    fn convert_config_only_takes_a_file_as_its_argument() {
+        let (entries, diagnostics) = overridden("serial_port=/dev/ttyS0\n", &["--convert-config", "-b", "1200"]);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(value(&entries, "baud_rate"), Some("1200"));
+        let (entries, diagnostics) = overridden("serial_port=/dev/ttyS0\n", &["--migrate-config", "-b", "1200"]);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(value(&entries, "baud_rate"), Some("1200"));
+        let (_, diagnostics) = overridden("serial_port=/dev/ttyS0\n", &["--convert-config", "old.cfg"]);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn wildcards_match_whole_names() {
+        assert!(wildcard_match("*.cfg", "vhf.cfg"));
+        assert!(!wildcard_match("*.cfg", "vhf.cfg.bak"));
+        assert!(wildcard_match("port?.cfg", "port1.cfg"));
+        assert!(!wildcard_match("port?.cfg", "port10.cfg"));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(!wildcard_match("exact.cfg", "Exact.cfg"));
+    }
+
+    #[test]
+    fn wildcards_skip_hidden_files_unless_asked() {
+        assert!(!wildcard_match("*.cfg", ".hidden.cfg"));
+        assert!(wildcard_match(".*.cfg", ".hidden.cfg"));
+    }
+
+    #[test]
+    fn templates_fill_in_unset_parameters() {
+        let contents = "template.base.baud_rate=9600\n\
+                        template.base.tcp_port=8001\n\
+                        template.radio.template=base\n\
+                        template.radio.baud_rate=1200\n\
+                        cross_connect0000.template=radio\n\
+                        cross_connect0000.tcp_port=8010\n\
+                        cross_connect0001.template=loop\n\
+                        template.loop.template=loop\n\
+                        cross_connect0002.template=missing\n";
+        let mut diagnostics = Vec::new();
+        let mut entries = parse("test.cfg", contents, &mut diagnostics);
+        apply_templates(&mut entries, &mut diagnostics);
+        let value = |key: &str| entries.iter().find(|e| e.key == key).map(|e| (e.value.as_str(), e.line));
+
+        // The nearer template wins, and the cross-connect's own setting over both
+        assert_eq!(value("cross_connect0000.baud_rate"), Some(("1200", 4)));
+        assert_eq!(value("cross_connect0000.tcp_port"), Some(("8010", 6)));
+        assert_eq!(value("cross_connect0001.baud_rate"), None);
+        let messages: Vec<(usize, &str)> = diagnostics.iter().map(|d| (d.line, d.message.as_str())).collect();
+        assert_eq!(messages, [
+            (8, "template loop inherits from itself (loop -> loop)"),
+            (9, "unknown template 'missing'"),
+        ]);
+    }
+
+    #[test]
+    fn includes_are_read_in_place() {
+        let dir = crate::tests::TempDir::new("include");
+        let main = dir.file("main.cfg", "log_level=3\ninclude=ports/*.cfg\ninclude=missing.cfg\npidfile=/run/x\n");
+        dir.file("ports/b.cfg", "cross_connect0001.serial_port=/dev/b\n");
+        dir.file("ports/a.cfg", "cross_connect0000.serial_port=/dev/a\ninclude=../main.cfg\n");
+        dir.file("ports/a.cfg.bak", "cross_connect0009.serial_port=/dev/old\n");
+
+        let mut diagnostics = Vec::new();
+        let entries = read_file(&main, &mut diagnostics).unwrap();
+
+        let keys: Vec<&str> = entries.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, ["log_level", "cross_connect0000.serial_port", "cross_connect0001.serial_port", "pidfile"]);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].starts_with("include ../main.cfg: ") && messages[0].ends_with("(include cycle)"),
+            "{}", messages[0]);
+        let missing = format!("include {}: Failed to read config file: ", dir.path().join("missing.cfg").display());
+        assert!(messages[1].starts_with(&missing), "{}", messages[1]);
+    }
+
+    fn entry(key: &str, value: &str, file: &str) -> Entry {
+        Entry { key: key.to_string(), value: value.to_string(), file: file.to_string(), line: 1, secret: None }
+    }
+
+    fn expanded(value: &str) -> Result<String, String> {
+        std::env::set_var("RAX25KB_TEST_DEVICE", "/dev/ttyUSB7");
+        std::env::set_var("RAX25KB_TEST_EMPTY", "");
+        std::env::remove_var("RAX25KB_TEST_UNSET");
+        expand_variables(value)
+    }
+
+    #[test]
+    fn variables_are_replaced_anywhere_in_a_value() {
+        assert_eq!(expanded("${RAX25KB_TEST_DEVICE}").as_deref(), Ok("/dev/ttyUSB7"));
+        assert_eq!(expanded("tnc at ${RAX25KB_TEST_DEVICE}!").as_deref(), Ok("tnc at /dev/ttyUSB7!"));
+        assert_eq!(expanded("${RAX25KB_TEST_EMPTY}").as_deref(), Ok(""));
+    }
+
+    #[test]
+    fn defaults_apply_to_unset_and_empty_variables() {
+        assert_eq!(expanded("${RAX25KB_TEST_UNSET:-9600}").as_deref(), Ok("9600"));
+        assert_eq!(expanded("${RAX25KB_TEST_EMPTY:-9600}").as_deref(), Ok("9600"));
+    }
+
+    #[test]
+    fn doubled_dollar_is_a_literal_dollar() {
+        assert_eq!(expanded("$$").as_deref(), Ok("$"));
+        assert_eq!(expanded("$${RAX25KB_TEST_DEVICE}").as_deref(), Ok("${RAX25KB_TEST_DEVICE}"));
+        assert_eq!(expanded("cost $5").as_deref(), Ok("cost $5"));
+    }
+
+    #[test]
+    fn bad_references_are_errors() {
+        assert_eq!(expanded("${RAX25KB_TEST_UNSET}").unwrap_err(), "environment variable RAX25KB_TEST_UNSET is not set");
+        assert_eq!(expanded("${RAX25KB_TEST_DEVICE").unwrap_err(), "unterminated ${");
+        assert_eq!(expanded("${BAD-NAME}").unwrap_err(), "invalid variable name 'BAD-NAME' in ${BAD-NAME}");
+    }
+
+    #[test]
+    fn interpolate_reads_files_and_reports_failures() {
+        let dir = crate::tests::TempDir::new("interpolate");
+        dir.file("secret.txt", "s3cret\n\n");
+        let config = dir.path().join("rax25kb.cfg").to_string_lossy().into_owned();
+        std::env::remove_var("RAX25KB_TEST_UNSET");
+        let mut entries = vec![
+            entry("pcap_file", "@file:secret.txt", &config),
+            entry("logfile", "@file:rax25kb-no-such-file", &config),
+            entry("pidfile", "${RAX25KB_TEST_UNSET}", &config),
+        ];
+        let mut diagnostics = Vec::new();
+        interpolate(&mut entries, &mut diagnostics);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].value, "s3cret");
+        assert_eq!(entries[0].secret.as_deref(), Some("@file:secret.txt"));
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].starts_with("logfile: cannot read ") && messages[0].contains("rax25kb-no-such-file"),
+            "{}", messages[0]);
+        assert_eq!(messages[1], "pidfile: environment variable RAX25KB_TEST_UNSET is not set");
+    }
+}
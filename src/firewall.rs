@@ -0,0 +1,317 @@
+// rax25kb - AX.25 KISS Bridge
+//
+// Copyright (C) 2025-2026 Kris Kirby, KE4AHR
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+//
+// KISS command firewall
+//
+// This file is part of rax25kb.
+//
+// rax25kb is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rax25kb is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rax25kb.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Every client of a cross-connect can send KISS commands to the TNC,
+// including Set Hardware and Return (0xFF), which takes the TNC out of KISS
+// mode for everyone. kiss_command_filter decides what happens to each
+// command type a client sends:
+//
+//   kiss_command_filter=return:drop, sethardware:drop, txdelay:rewrite=30
+//
+// kiss_client_filter overrides it for some clients, by address, network or
+// `pty` for the pty application; the first entry that matches applies:
+//
+//   kiss_client_filter=127.0.0.1 *:allow; 192.168.1.0/24 data:drop
+//
+// A client's own rules come first, then those of the cross-connect; within
+// each an entry for the command wins over `*`. Anything not matched is allowed.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::kiss;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Allow,
+    Drop,
+    Rewrite(u8),    // Send a parameter command with this value instead
+}
+
+// What a rule applies to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Kind(u8),       // Low nibble of the command byte
+    Return,         // 0xFF, which has no port
+    Any,
+}
+
+const NAMES: &[(&str, u8)] = &[
+    ("data", 0x00),
+    ("txdelay", kiss::CMD_TXDELAY),
+    ("persist", kiss::CMD_PERSIST),
+    ("slottime", kiss::CMD_SLOTTIME),
+    ("txtail", kiss::CMD_TXTAIL),
+    ("fullduplex", kiss::CMD_FULLDUPLEX),
+    ("sethardware", 0x06),
+    ("ackmode", kiss::CMD_ACKMODE),
+    ("poll", kiss::CMD_POLL),
+];
+
+const RETURN: u8 = 0xFF;
+
+type Rules = Vec<(Command, Action)>;
+
+// Who sent a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Client {
+    Address(SocketAddr),
+    Pty,
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Client::Address(address) => write!(f, "{}", address),
+            Client::Pty => write!(f, "pty"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClientMatch {
+    Network(IpAddr, u8),    // Address and prefix length
+    Pty,
+}
+
+impl ClientMatch {
+    fn matches(&self, client: Client) -> bool {
+        match (self, client) {
+            (ClientMatch::Pty, Client::Pty) => true,
+            (ClientMatch::Network(network, prefix), Client::Address(address)) => {
+                // An IPv4 client may show up as an IPv4-mapped IPv6 address
+                let address = match address.ip() {
+                    IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(IpAddr::V6(v6)),
+                    v4 => v4,
+                };
+                match (network, address) {
+                    (IpAddr::V4(network), IpAddr::V4(address)) => {
+                        let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                        u32::from(*network) & mask == u32::from(address) & mask
+                    }
+                    (IpAddr::V6(network), IpAddr::V6(address)) => {
+                        let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                        u128::from(*network) & mask == u128::from(address) & mask
+                    }
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    rules: Rules,
+    clients: Vec<(ClientMatch, Rules)>,
+}
+
+impl Filter {
+    // Filter from the kiss_command_filter and kiss_client_filter settings
+    pub fn parse(rules: Option<&str>, clients: Option<&str>) -> Result<Self, String> {
+        Ok(Filter {
+            rules: rules.map(parse_rules).transpose()?.unwrap_or_default(),
+            clients: clients.map(parse_clients).transpose()?.unwrap_or_default(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.clients.is_empty()
+    }
+
+    // What to do with a frame from `client` starting with `command_byte`
+    pub fn verdict(&self, client: Option<Client>, command_byte: u8) -> Action {
+        let own = client.and_then(|client| self.clients.iter()
+            .find(|(pattern, _)| pattern.matches(client))
+            .map(|(_, rules)| rules));
+        own.into_iter().chain([&self.rules])
+            .find_map(|rules| lookup(rules, command_byte))
+            .unwrap_or(Action::Allow)
+    }
+}
+
+fn lookup(rules: &Rules, command_byte: u8) -> Option<Action> {
+    let command = if command_byte == RETURN { Command::Return } else { Command::Kind(kiss::command(command_byte)) };
+    rules.iter().find(|(rule, _)| *rule == command)
+        .or_else(|| rules.iter().find(|(rule, _)| *rule == Command::Any))
+        .map(|&(_, action)| action)
+}
+
+// Name of the command in `command_byte`, for log messages
+pub fn command_name(command_byte: u8) -> String {
+    if command_byte == RETURN {
+        return "return".to_string();
+    }
+    let command = kiss::command(command_byte);
+    NAMES.iter().find(|&&(_, value)| value == command)
+        .map(|&(name, _)| name.to_string())
+        .unwrap_or_else(|| format!("command {}", command))
+}
+
+// "return:drop, txdelay:rewrite=30, *:allow"
+fn parse_rules(text: &str) -> Result<Rules, String> {
+    let mut rules = Rules::new();
+    for rule in text.split([',', ' ', '\t']).filter(|rule| !rule.is_empty()) {
+        let (name, action) = rule.split_once(':')
+            .ok_or_else(|| format!("'{}': expected COMMAND:ACTION, e.g. return:drop", rule))?;
+        let command = match name.to_lowercase().as_str() {
+            "*" => Command::Any,
+            "return" => Command::Return,
+            name => match NAMES.iter().find(|&&(known, _)| known == name) {
+                Some(&(_, kind)) => Command::Kind(kind),
+                None => match name.parse::<u8>() {
+                    Ok(kind) if kind <= 15 => Command::Kind(kind),
+                    _ => return Err(format!("'{}': unknown KISS command '{}' (data, txdelay, persist, \
+                        slottime, txtail, fullduplex, sethardware, ackmode, poll, return, 0-15 or *)", rule, name)),
+                },
+            },
+        };
+        let action = match action.to_lowercase().as_str() {
+            "allow" => Action::Allow,
+            "drop" => Action::Drop,
+            action => match action.strip_prefix("rewrite=").map(str::parse::<u8>) {
+                Some(Ok(value)) => match command {
+                    Command::Kind(kind) if kiss::param_name(kind).is_some() => Action::Rewrite(value),
+                    _ => return Err(format!("'{}': only txdelay, persist, slottime, txtail and \
+                        fullduplex can be rewritten", rule)),
+                },
+                Some(Err(_)) => return Err(format!("'{}': rewrite needs a value from 0 to 255", rule)),
+                None => return Err(format!("'{}': unknown action '{}' (allow, drop or rewrite=VALUE)", rule, action)),
+            },
+        };
+        if rules.iter().any(|&(other, _)| other == command) {
+            return Err(format!("'{}': {} already has a rule", rule, name));
+        }
+        rules.push((command, action));
+    }
+    Ok(rules)
+}
+
+// "127.0.0.1 *:allow; 192.168.1.0/24 data:drop; pty return:allow"
+fn parse_clients(text: &str) -> Result<Vec<(ClientMatch, Rules)>, String> {
+    let mut clients = Vec::new();
+    for entry in text.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (client, rules) = entry.split_once([' ', '\t'])
+            .ok_or_else(|| format!("'{}': expected a client address followed by its rules", entry))?;
+        let pattern = if client.eq_ignore_ascii_case("pty") {
+            ClientMatch::Pty
+        } else {
+            let (address, prefix) = client.split_once('/').unwrap_or((client, ""));
+            let address: IpAddr = address.parse()
+                .map_err(|_| format!("'{}' is not an address, network (address/bits) or pty", client))?;
+            let bits = if address.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                "" => bits,
+                prefix => prefix.parse().ok().filter(|&prefix| prefix <= bits)
+                    .ok_or_else(|| format!("'{}': prefix length must be 0-{}", client, bits))?,
+            };
+            ClientMatch::Network(address, prefix)
+        };
+        clients.push((pattern, parse_rules(rules)?));
+    }
+    Ok(clients)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(address: &str) -> Option<Client> {
+        Some(Client::Address(address.parse().unwrap()))
+    }
+
+    #[test]
+    fn client_rules_beat_cross_connect_rules() {
+        let filter = Filter::parse(Some("return:drop, data:drop"),
+            Some("127.0.0.1 return:allow; pty data:allow")).unwrap();
+        assert_eq!(filter.verdict(client("127.0.0.1:5000"), RETURN), Action::Allow);
+        assert_eq!(filter.verdict(client("127.0.0.2:5000"), RETURN), Action::Drop);
+        assert_eq!(filter.verdict(None, RETURN), Action::Drop);
+        // The client's rules don't cover data, so the cross-connect's apply
+        assert_eq!(filter.verdict(client("127.0.0.1:5000"), 0x00), Action::Drop);
+        assert_eq!(filter.verdict(Some(Client::Pty), 0x00), Action::Allow);
+        assert_eq!(filter.verdict(Some(Client::Pty), RETURN), Action::Drop);
+    }
+
+    #[test]
+    fn specific_command_beats_wildcard() {
+        let filter = Filter::parse(Some("*:drop, data:allow, txdelay:rewrite=30"), None).unwrap();
+        assert_eq!(filter.verdict(None, 0x00), Action::Allow);
+        assert_eq!(filter.verdict(None, 0x10), Action::Allow);
+        assert_eq!(filter.verdict(None, 0x01), Action::Rewrite(30));
+        assert_eq!(filter.verdict(None, 0x31), Action::Rewrite(30));
+        assert_eq!(filter.verdict(None, 0x06), Action::Drop);
+        assert_eq!(filter.verdict(None, RETURN), Action::Drop);
+        assert_eq!(Filter::parse(None, None).unwrap().verdict(None, RETURN), Action::Allow);
+    }
+
+    #[test]
+    fn client_networks() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("192.168.1.0/24", "192.168.1.77:8001", true),
+            ("192.168.1.0/24", "192.168.2.77:8001", false),
+            ("192.168.1.0/24", "[::ffff:192.168.1.77]:8001", true),
+            ("10.0.0.1/32", "10.0.0.1:8001", true),
+            ("10.0.0.1/32", "10.0.0.2:8001", false),
+            ("10.0.0.1", "10.0.0.1:8001", true),
+            ("0.0.0.0/0", "203.0.113.9:8001", true),
+            ("0.0.0.0/0", "[2001:db8::1]:8001", false),
+            ("2001:db8::/32", "[2001:db8:5::1]:8001", true),
+            ("2001:db8::/32", "[2001:db9::1]:8001", false),
+            ("::/0", "[2001:db8::1]:8001", true),
+            ("pty", "127.0.0.1:8001", false),
+        ];
+        for &(network, address, matches) in cases {
+            let filter = Filter::parse(None, Some(&format!("{} return:drop", network))).unwrap();
+            let expected = if matches { Action::Drop } else { Action::Allow };
+            assert_eq!(filter.verdict(client(address), RETURN), expected, "{} {}", network, address);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let rules: &[(&str, &str)] = &[
+            ("return:rewrite=5", "'return:rewrite=5': only txdelay, persist, slottime, txtail and fullduplex can be rewritten"),
+            ("data:rewrite=5", "'data:rewrite=5': only txdelay, persist, slottime, txtail and fullduplex can be rewritten"),
+            ("*:rewrite=5", "'*:rewrite=5': only txdelay, persist, slottime, txtail and fullduplex can be rewritten"),
+            ("txdelay:rewrite=256", "'txdelay:rewrite=256': rewrite needs a value from 0 to 255"),
+            ("return:drop, return:allow", "'return:allow': return already has a rule"),
+            ("1:drop, txdelay:allow", "'txdelay:allow': txdelay already has a rule"),
+            ("return", "'return': expected COMMAND:ACTION, e.g. return:drop"),
+            ("return:reject", "'return:reject': unknown action 'reject' (allow, drop or rewrite=VALUE)"),
+        ];
+        for &(text, message) in rules {
+            assert_eq!(Filter::parse(Some(text), None).unwrap_err(), message);
+        }
+        let clients: &[(&str, &str)] = &[
+            ("10.0.0.0/33 return:drop", "'10.0.0.0/33': prefix length must be 0-32"),
+            ("::/129 return:drop", "'::/129': prefix length must be 0-128"),
+            ("somehost return:drop", "'somehost' is not an address, network (address/bits) or pty"),
+            ("10.0.0.1", "'10.0.0.1': expected a client address followed by its rules"),
+        ];
+        for &(text, message) in clients {
+            assert_eq!(Filter::parse(None, Some(text)).unwrap_err(), message);
+        }
+    }
+}
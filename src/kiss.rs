@@ -0,0 +1,317 @@
+// rax25kb - AX.25 KISS Bridge
+//
+// Copyright (C) 2025-2026 Kris Kirby, KE4AHR
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+//
+// KISS framing codec
+//
+// This file is part of rax25kb.
+//
+// rax25kb is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rax25kb is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rax25kb.  If not, see <https://www.gnu.org/licenses/>.
+//
+// A KISS frame on the wire is FEND, the escaped frame, FEND. Inside the
+// frame FEND is sent as FESC TFEND and FESC as FESC TFESC. The decoded
+// frame is the command byte ((port << 4) | command) followed by the
+// payload, which for data frames is one raw AX.25 frame.
+//
+// Everything inside rax25kb that looks at frame contents (PCAP, AGW,
+// logging, channel filtering) works on decoded frames. Escaping is only
+// applied by encode() when a frame goes back out to serial or TCP.
+
+use std::fmt;
+
+pub const FEND: u8 = 0xC0;
+pub const FESC: u8 = 0xDB;
+pub const TFEND: u8 = 0xDC;
+pub const TFESC: u8 = 0xDD;
+
+// Channel parameters: [command][value]. Times are in 10 ms units, persistence
+// is p = (value + 1) / 256 and full duplex is on for any nonzero value.
+pub const CMD_TXDELAY: u8 = 0x01;
+pub const CMD_PERSIST: u8 = 0x02;
+pub const CMD_SLOTTIME: u8 = 0x03;
+pub const CMD_TXTAIL: u8 = 0x04;
+pub const CMD_FULLDUPLEX: u8 = 0x05;
+
+// ACKMODE: [command][sequence hi][sequence lo][AX.25]; the TNC returns the
+// command and sequence once the frame has been transmitted
+pub const CMD_ACKMODE: u8 = 0x0C;
+
+// XKISS poll (G8BPQ polled mode): the host sends it to an address, the TNC
+// answers with a queued frame or echoes the poll
+pub const CMD_POLL: u8 = 0x0E;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodeError {
+    Empty,
+    BadEscape(usize),      // FESC at this offset not followed by TFEND/TFESC
+    TruncatedEscape,       // Frame ends with FESC
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "empty frame"),
+            DecodeError::BadEscape(offset) => write!(f, "invalid escape at offset {}", offset),
+            DecodeError::TruncatedEscape => write!(f, "frame ends inside an escape"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// Escape FEND and FESC bytes for transmission inside a frame
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + 8);
+    for &byte in data {
+        match byte {
+            FEND => output.extend_from_slice(&[FESC, TFEND]),
+            FESC => output.extend_from_slice(&[FESC, TFESC]),
+            _ => output.push(byte),
+        }
+    }
+    output
+}
+
+// Wrap a decoded frame (command byte + payload) for the wire
+pub fn encode(frame: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(frame.len() + 10);
+    output.push(FEND);
+    output.extend_from_slice(&escape(frame));
+    output.push(FEND);
+    output
+}
+
+// Undo FESC TFEND / FESC TFESC on the bytes between two FENDs
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    if data.is_empty() {
+        return Err(DecodeError::Empty);
+    }
+    let mut output = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().enumerate();
+    while let Some((offset, &byte)) = bytes.next() {
+        if byte != FESC {
+            output.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some((_, &TFEND)) => output.push(FEND),
+            Some((_, &TFESC)) => output.push(FESC),
+            Some(_) => return Err(DecodeError::BadEscape(offset)),
+            None => return Err(DecodeError::TruncatedEscape),
+        }
+    }
+    Ok(output)
+}
+
+// Decoded command byte of an escaped frame, without decoding the rest
+pub fn command_byte(data: &[u8]) -> Option<u8> {
+    match data {
+        [FESC, TFEND, ..] => Some(FEND),
+        [FESC, TFESC, ..] => Some(FESC),
+        [FESC, ..] => None,
+        [byte, ..] => Some(*byte),
+        [] => None,
+    }
+}
+
+// Shortest valid AX.25 frame: destination and source address plus control
+pub const AX25_MIN_LEN: usize = 15;
+
+// A data frame too short to hold an AX.25 header, typically the remains of a
+// frame that lost bytes on the serial line
+pub fn is_runt(frame: &[u8]) -> bool {
+    match frame.first() {
+        Some(&command_byte) if command(command_byte) == 0 => frame.len() < 1 + AX25_MIN_LEN,
+        Some(_) => false,
+        None => true,
+    }
+}
+
+// Port (high nibble) of a command byte
+pub fn port(command: u8) -> u8 {
+    (command >> 4) & 0x0F
+}
+
+// Command (low nibble) of a command byte
+pub fn command(command: u8) -> u8 {
+    command & 0x0F
+}
+
+// Name of a channel parameter command, for log messages
+pub fn param_name(command: u8) -> Option<&'static str> {
+    match command {
+        CMD_TXDELAY => Some("TXDELAY"),
+        CMD_PERSIST => Some("PERSIST"),
+        CMD_SLOTTIME => Some("SLOTTIME"),
+        CMD_TXTAIL => Some("TXTAIL"),
+        CMD_FULLDUPLEX => Some("FULLDUPLEX"),
+        _ => None,
+    }
+}
+
+// Output of the deframer for one FEND-delimited unit
+#[derive(Debug, PartialEq)]
+pub enum Deframed {
+    Frame(Vec<u8>),                                  // Frame content between FENDs, still escaped
+    Oversize { command: Option<u8>, len: usize },    // Frame longer than max_len, dropped
+    Resync(usize),                                   // Bytes skipped before the first FEND
+}
+
+// Streaming KISS deframer for serial and TCP byte streams.
+//
+// Bytes arrive in arbitrary chunks. Every FEND is a frame boundary, so a lost
+// or doubled FEND costs at most the frames next to it and never flips the
+// framing for the rest of the stream. Empty frames (back-to-back FENDs) are
+// skipped. A frame that grows past max_len is dropped up to the next FEND.
+pub struct Deframer {
+    buffer: Vec<u8>,
+    max_len: usize,
+    synced: bool,                        // Seen a FEND, so buffer holds the start of a frame
+    skipped: usize,                      // Bytes seen before the first FEND
+    oversize: Option<(Option<u8>, usize)>,  // Command byte and length of the frame being dropped
+}
+
+impl Deframer {
+    pub fn new(max_len: usize) -> Self {
+        Deframer {
+            buffer: Vec::new(),
+            max_len,
+            synced: false,
+            skipped: 0,
+            oversize: None,
+        }
+    }
+
+    // Feed a chunk; returns completed frames and framing errors in stream order
+    pub fn push(&mut self, data: &[u8]) -> Vec<Deframed> {
+        let mut output = Vec::new();
+        for &byte in data {
+            if byte == FEND {
+                if let Some((command, len)) = self.oversize.take() {
+                    output.push(Deframed::Oversize { command, len });
+                } else if !self.buffer.is_empty() {
+                    output.push(Deframed::Frame(std::mem::take(&mut self.buffer)));
+                }
+                if self.skipped > 0 {
+                    output.push(Deframed::Resync(self.skipped));
+                    self.skipped = 0;
+                }
+                self.synced = true;
+            } else if !self.synced {
+                self.skipped += 1;
+            } else if let Some((_, ref mut len)) = self.oversize {
+                *len += 1;
+            } else {
+                self.buffer.push(byte);
+                if self.buffer.len() > self.max_len {
+                    self.oversize = Some((command_byte(&self.buffer), self.buffer.len()));
+                    self.buffer = Vec::new();
+                }
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let frames: &[&[u8]] = &[
+            &[0x00, 0x41, 0x42],
+            &[0x00, FEND, 0x01, FESC, 0x02],
+            &[0x10, FESC, FEND, FEND, FESC],
+            &[FEND],
+        ];
+        for &frame in frames {
+            let wire = encode(frame);
+            assert_eq!(wire.first(), Some(&FEND));
+            assert_eq!(wire.last(), Some(&FEND));
+            let inner = &wire[1..wire.len() - 1];
+            assert!(!inner.contains(&FEND), "{:02x?} left a FEND inside", frame);
+            assert_eq!(decode(inner), Ok(frame.to_vec()));
+            assert_eq!(command_byte(inner), Some(frame[0]));
+        }
+        assert_eq!(escape(&[0x00, FEND, FESC]), vec![0x00, FESC, TFEND, FESC, TFESC]);
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(decode(&[0x00, 0x41, FESC]), Err(DecodeError::TruncatedEscape));
+        assert_eq!(decode(&[0x00, FESC, 0x41]), Err(DecodeError::BadEscape(1)));
+        assert_eq!(decode(&[0x00, FESC, TFEND, FESC, FESC]), Err(DecodeError::BadEscape(3)));
+        assert_eq!(command_byte(&[FESC, 0x41]), None);
+    }
+
+    #[test]
+    fn runts() {
+        let mut frame = vec![0x00];
+        frame.extend_from_slice(&[0x40; AX25_MIN_LEN - 1]);
+        assert!(is_runt(&frame));
+        frame.push(0x03);
+        assert!(!is_runt(&frame));
+        assert!(!is_runt(&[0x01, 0x32]));     // Parameters are not data frames
+        assert!(is_runt(&[]));
+    }
+
+    #[test]
+    fn deframer_joins_a_frame_split_across_chunks() {
+        let mut deframer = Deframer::new(64);
+        assert_eq!(deframer.push(&[FEND, 0x00, 0x41]), vec![]);
+        assert_eq!(deframer.push(&[0x42]), vec![]);
+        assert_eq!(deframer.push(&[0x43, FEND, 0x00]), vec![Deframed::Frame(vec![0x00, 0x41, 0x42, 0x43])]);
+        assert_eq!(deframer.push(&[0x44, FEND]), vec![Deframed::Frame(vec![0x00, 0x44])]);
+    }
+
+    #[test]
+    fn deframer_skips_empty_frames() {
+        let mut deframer = Deframer::new(64);
+        assert_eq!(deframer.push(&[FEND, FEND, FEND, 0x00, 0x41, FEND, FEND, 0x10, 0x42, FEND]), vec![
+            Deframed::Frame(vec![0x00, 0x41]),
+            Deframed::Frame(vec![0x10, 0x42]),
+        ]);
+    }
+
+    #[test]
+    fn deframer_drops_oversize_frames() {
+        let mut deframer = Deframer::new(4);
+        let mut data = vec![FEND, 0x20];
+        data.extend_from_slice(&[0x41; 9]);
+        data.extend_from_slice(&[FEND, 0x00, 0x42, FEND]);
+        assert_eq!(deframer.push(&data), vec![
+            Deframed::Oversize { command: Some(0x20), len: 10 },
+            Deframed::Frame(vec![0x00, 0x42]),
+        ]);
+    }
+
+    #[test]
+    fn deframer_resyncs_after_garbage() {
+        let mut deframer = Deframer::new(64);
+        assert_eq!(deframer.push(b"garbage"), vec![]);
+        assert_eq!(deframer.push(&[0x41, FEND, 0x00, 0x42, FEND]), vec![
+            Deframed::Resync(8),
+            Deframed::Frame(vec![0x00, 0x42]),
+        ]);
+        // A lost FEND only costs the frames around it
+        assert_eq!(deframer.push(&[0x00, 0x43, 0x00, 0x44, FEND, 0x00, 0x45, FEND]), vec![
+            Deframed::Frame(vec![0x00, 0x43, 0x00, 0x44]),
+            Deframed::Frame(vec![0x00, 0x45]),
+        ]);
+    }
+}
@@ -35,35 +35,44 @@
 // See the included documentation for more information.
 
 use std::collections::{HashMap, VecDeque};
-use std::fs::{self, File, OpenOptions};
+use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, Mutex};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use serialport::SerialPort;
 
-const KISS_FEND: u8 = 0xC0;
-const KISS_FESC: u8 = 0xDB;
-const KISS_TFEND: u8 = 0xDC;
-#[allow(dead_code)]
-const KISS_TFESC: u8 = 0xDD;
-
-#[derive(Debug, Clone)]
+mod config;
+mod firewall;
+mod kiss;
+mod pipeline;
+mod ports;
+#[cfg(unix)]
+mod pty;
+mod script;
+mod transport;
+
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)] // Some fields used conditionally based on configuration
 struct CrossConnect {
-    id: String,
+    id: String,                  // Name shown in logs: `name` if set, else the slot
+    slot: String,                // cross_connectNNNN the settings were read under
     serial_port: String,
     baud_rate: u32,
     flow_control: FlowControl,
     stop_bits: StopBits,
     data_bits: DataBits,
     parity: Parity,
+    init_script: Option<String>, // Sent when the device is opened or reopened (see script.rs)
+    exit_script: Option<String>, // Sent before the device is closed
     tcp_address: String,
     tcp_port: u16,
     tcp_mode: TcpMode,
     tcp_server_address: Option<String>,
     tcp_server_port: Option<u16>,
+    pty_link: Option<String>,    // Path linked to the slave of a pty endpoint
     kiss_port: u8,
     kiss_chan: i32,              // -1 = all channels, 0-15 = specific channel
     kiss_copy: bool,             // Enable KISSCOPY (broadcast between clients)
@@ -73,6 +82,17 @@ struct CrossConnect {
     xkiss_polling: bool,
     xkiss_poll_timer_ms: u64,
     xkiss_rx_buffer_size: usize,
+    xkiss_emulation: bool,       // Act as a polled XKISS TNC toward TCP host applications
+    ackmode_emulation: bool,     // Ack ACKMODE frames ourselves for TNCs without ACKMODE
+    txdelay: Option<u8>,         // Managed KISS parameters, sent to the TNC when the
+    persist: Option<u8>,         //   port is set up, after a reopen and every
+    slottime: Option<u8>,        //   kiss_param_interval
+    txtail: Option<u8>,
+    fullduplex: Option<bool>,
+    kiss_param_interval: u64,    // Seconds between re-sends, 0 = not re-sent
+    kiss_param_policy: ParamPolicy,  // Clients' commands for managed parameters
+    kiss_command_filter: Option<String>,  // Allow, drop or rewrite clients' KISS commands (firewall.rs)
+    kiss_client_filter: Option<String>,   // Per-client overrides of kiss_command_filter
     serial_to_serial: Option<String>,
     tcp_to_tcp_dangerous: bool,
     tcp_to_tcp_also_dangerous: bool,
@@ -82,11 +102,53 @@ struct CrossConnect {
     dump_ax25: bool,
     raw_copy: bool,
     reframe_large_packets: bool,
+    max_frame_len: usize,        // Longest KISS frame accepted from serial/TCP, in wire bytes
     is_primary_port: bool,       // True if this is KISS port 0 (controls serial params)
     agw_port: u8,                // AGW port number (0-255, default: 0)
     agw_enable: bool,            // Enable AGW for this cross-connect (default: false)
 }
 
+impl CrossConnect {
+    // KISS port nibble used on the serial line: the XKISS address in XKISS mode
+    fn wire_port(&self) -> u8 {
+        match self.xkiss_port {
+            Some(xkiss_port) if self.xkiss_mode => xkiss_port & 0x0F,
+            _ => self.kiss_port,
+        }
+    }
+
+    // Managed KISS parameters as (command, value)
+    fn kiss_params(&self) -> Vec<(u8, u8)> {
+        [
+            (kiss::CMD_TXDELAY, self.txdelay),
+            (kiss::CMD_PERSIST, self.persist),
+            (kiss::CMD_SLOTTIME, self.slottime),
+            (kiss::CMD_TXTAIL, self.txtail),
+            (kiss::CMD_FULLDUPLEX, self.fullduplex.map(u8::from)),
+        ].into_iter().filter_map(|(command, value)| Some((command, value?))).collect()
+    }
+
+    // Value rax25kb keeps for parameter `command`, if it manages it
+    fn kiss_param(&self, command: u8) -> Option<u8> {
+        self.kiss_params().into_iter().find(|&(managed, _)| managed == command).map(|(_, value)| value)
+    }
+
+    // Same settings for opening and deframing the serial device, so a
+    // demultiplexer opened with `other` can be kept for this cross-connect
+    fn same_line(&self, other: &CrossConnect) -> bool {
+        self.serial_port == other.serial_port
+            && self.baud_rate == other.baud_rate
+            && self.flow_control == other.flow_control
+            && self.stop_bits == other.stop_bits
+            && self.data_bits == other.data_bits
+            && self.parity == other.parity
+            && self.init_script == other.init_script
+            && self.exit_script == other.exit_script
+            && self.raw_copy == other.raw_copy
+            && self.max_frame_len == other.max_frame_len
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Config {
     cross_connects: Vec<CrossConnect>,
@@ -102,6 +164,7 @@ struct Config {
     agw_server_address: String,  // AGW bind address (default: "0.0.0.0")
     agw_server_port: u16,        // AGW port (default: 8000)
     agw_max_clients: usize,      // Max AGW clients (default: 3)
+    secrets: HashMap<String, String>,   // Keys read with @file:, and the reference
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -111,6 +174,14 @@ enum TcpMode {
     None,
 }
 
+// What happens to a client's command for a KISS parameter rax25kb manages
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParamPolicy {
+    Pass,       // Sent to the TNC; the managed value is restored at the next re-send
+    Override,   // Sent with the managed value instead
+    Block,      // Dropped
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum FlowControl {
     None,
@@ -139,36 +210,54 @@ enum DataBits {
 }
 
 impl Config {
-    fn from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let contents = fs::read_to_string(path)
-            .map_err(|e| format!("Failed to read config file '{}': {}", path, e))?;
-        
-        let mut config_map = HashMap::new();
-        
-        for line in contents.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') {
-                continue;
-            }
-            if let Some((key, value)) = line.split_once('=') {
-                let key = key.trim();
-                let mut value = value.trim();
-                if value.starts_with('"') && value.ends_with('"') && value.len() >= 2 {
-                    value = &value[1..value.len()-1];
+    // Read and validate a configuration file. Every problem found is
+    // returned, each with the file and line it came from.
+    fn from_file(path: &str) -> Result<Self, config::ConfigError> {
+        Self::load(path, &[])
+    }
+    
+    // As from_file(), with the settings given on the command line (`--set`
+    // and the short options) applied over the file before validation
+    fn load(path: &str, args: &[String]) -> Result<Self, config::ConfigError> {
+        let mut diagnostics = Vec::new();
+        let mut entries = config::read_file(path, &mut diagnostics)?;
+        config::apply_overrides(&mut entries, args, &mut diagnostics);
+        config::interpolate(&mut entries, &mut diagnostics);
+        diagnostics.extend(config::check_entries(&mut entries));
+        if !diagnostics.is_empty() {
+            // Group by file (in the order first reported), then by line
+            let mut files: Vec<String> = Vec::new();
+            for diagnostic in &diagnostics {
+                if !files.contains(&diagnostic.file) {
+                    files.push(diagnostic.file.clone());
                 }
-                config_map.insert(key.to_string(), value.to_string());
             }
+            diagnostics.sort_by_key(|d| (files.iter().position(|f| *f == d.file), d.line));
+        }
+
+        // check_entries() has dropped the entries it reported, so the second
+        // pass runs on the rest and every problem is reported at once
+        let config = Self::from_entries(&entries);
+        diagnostics.extend(config::check_config(&config, &entries));
+        if !diagnostics.is_empty() {
+            return Err(diagnostics.into());
         }
+        Ok(config)
+    }
+    
+    // Build the configuration from entries that have passed check_entries()
+    fn from_entries(entries: &[config::Entry]) -> Self {
+        let config_map: HashMap<String, String> = entries.iter()
+            .map(|entry| (entry.key.clone(), entry.value.clone()))
+            .collect();
         
         let mut cross_connects = Vec::new();
         let mut cc_map: HashMap<String, HashMap<String, String>> = HashMap::new();
         
         for (key, value) in &config_map {
-            if key.starts_with("cross_connect") && key.len() >= 17 {
-                let cc_id = &key[..17];
-                let param = &key[18..];
+            if let Some((cc_id, param)) = config::split_cross_connect_key(key) {
                 cc_map.entry(cc_id.to_string())
-                    .or_insert_with(HashMap::new)
+                    .or_default()
                     .insert(param.to_string(), value.clone());
             }
         }
@@ -176,9 +265,8 @@ impl Config {
         if cc_map.is_empty() {
             let default_cc = CrossConnect {
                 id: "cross_connect0000".to_string(),
-                serial_port: config_map.get("serial_port")
-                    .ok_or("Missing required config: serial_port")?
-                    .clone(),
+                slot: "cross_connect0000".to_string(),
+                serial_port: config_map.get("serial_port").cloned().unwrap_or_default(),
                 baud_rate: config_map.get("baud_rate")
                     .and_then(|v| v.parse().ok())
                     .unwrap_or(9600),
@@ -186,6 +274,8 @@ impl Config {
                 stop_bits: parse_stop_bits(config_map.get("stop_bits")),
                 data_bits: parse_data_bits(config_map.get("data_bits")),
                 parity: parse_parity(config_map.get("parity")),
+                init_script: None,
+                exit_script: None,
                 tcp_address: config_map.get("tcp_address")
                     .cloned()
                     .unwrap_or_else(|| "0.0.0.0".to_string()),
@@ -196,6 +286,7 @@ impl Config {
                 tcp_server_address: config_map.get("tcp_server_address").cloned(),
                 tcp_server_port: config_map.get("tcp_server_port")
                     .and_then(|v| v.parse().ok()),
+                pty_link: None,
                 kiss_port: 0,
                 kiss_chan: -1,  // Default: all channels
                 kiss_copy: false,  // Default: no KISSCOPY
@@ -208,6 +299,17 @@ impl Config {
                     .and_then(|v| v.parse().ok())
                     .map(|s: usize| s.clamp(4096, 1048576))
                     .unwrap_or(16384),
+                xkiss_emulation: parse_bool(config_map.get("xkiss_emulation")),
+                ackmode_emulation: parse_bool(config_map.get("ackmode_emulation")),
+                txdelay: None,
+                persist: None,
+                slottime: None,
+                txtail: None,
+                fullduplex: None,
+                kiss_param_interval: 0,
+                kiss_param_policy: ParamPolicy::Pass,
+                kiss_command_filter: None,
+                kiss_client_filter: None,
                 serial_to_serial: None,
                 tcp_to_tcp_dangerous: parse_bool(config_map.get("tcp_to_tcp_dangerous")),
                 tcp_to_tcp_also_dangerous: parse_bool(config_map.get("tcp_to_tcp_also_dangerous")),
@@ -217,6 +319,10 @@ impl Config {
                 dump_ax25: parse_bool(config_map.get("dump_ax25")),
                 raw_copy: parse_bool(config_map.get("raw_copy")),
                 reframe_large_packets: parse_bool(config_map.get("reframe_large_packets")),
+                max_frame_len: config_map.get("max_frame_len")
+                    .and_then(|v| v.parse().ok())
+                    .map(|s: usize| s.clamp(64, 65536))
+                    .unwrap_or(1024),
                 is_primary_port: true,  // Legacy mode is always primary
                 agw_port: 0,            // Default AGW port
                 agw_enable: false,      // AGW disabled by default in legacy mode
@@ -225,10 +331,9 @@ impl Config {
         } else {
             for (cc_id, params) in cc_map {
                 let cc = CrossConnect {
-                    id: cc_id.clone(),
-                    serial_port: params.get("serial_port")
-                        .ok_or(format!("Missing serial_port for {}", cc_id))?
-                        .clone(),
+                    id: params.get("name").cloned().unwrap_or_else(|| cc_id.clone()),
+                    slot: cc_id.clone(),
+                    serial_port: params.get("serial_port").cloned().unwrap_or_default(),
                     baud_rate: params.get("baud_rate")
                         .and_then(|v| v.parse().ok())
                         .unwrap_or(9600),
@@ -236,6 +341,8 @@ impl Config {
                     stop_bits: parse_stop_bits(params.get("stop_bits")),
                     data_bits: parse_data_bits(params.get("data_bits")),
                     parity: parse_parity(params.get("parity")),
+                    init_script: params.get("init_script").cloned(),
+                    exit_script: params.get("exit_script").cloned(),
                     tcp_address: params.get("tcp_address")
                         .cloned()
                         .unwrap_or_else(|| "0.0.0.0".to_string()),
@@ -246,6 +353,7 @@ impl Config {
                     tcp_server_address: params.get("tcp_server_address").cloned(),
                     tcp_server_port: params.get("tcp_server_port")
                         .and_then(|v| v.parse().ok()),
+                    pty_link: params.get("pty_link").cloned(),
                     kiss_port: params.get("kiss_port")
                         .and_then(|v| v.parse().ok())
                         .unwrap_or(0),
@@ -265,6 +373,19 @@ impl Config {
                         .and_then(|v| v.parse().ok())
                         .map(|s: usize| s.clamp(4096, 1048576))
                         .unwrap_or(16384),
+                    xkiss_emulation: parse_bool(params.get("xkiss_emulation")),
+                    ackmode_emulation: parse_bool(params.get("ackmode_emulation")),
+                    txdelay: params.get("txdelay").and_then(|v| v.parse().ok()),
+                    persist: params.get("persist").and_then(|v| v.parse().ok()),
+                    slottime: params.get("slottime").and_then(|v| v.parse().ok()),
+                    txtail: params.get("txtail").and_then(|v| v.parse().ok()),
+                    fullduplex: params.get("fullduplex").map(|v| parse_bool(Some(v))),
+                    kiss_param_interval: params.get("kiss_param_interval")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(0),
+                    kiss_param_policy: parse_param_policy(params.get("kiss_param_policy")),
+                    kiss_command_filter: params.get("kiss_command_filter").cloned(),
+                    kiss_client_filter: params.get("kiss_client_filter").cloned(),
                     serial_to_serial: params.get("serial_to_serial").cloned(),
                     tcp_to_tcp_dangerous: parse_bool(params.get("tcp_to_tcp_dangerous")),
                     tcp_to_tcp_also_dangerous: parse_bool(params.get("tcp_to_tcp_also_dangerous")),
@@ -274,6 +395,10 @@ impl Config {
                     dump_ax25: parse_bool(params.get("dump_ax25")),
                     raw_copy: parse_bool(params.get("raw_copy")),
                     reframe_large_packets: parse_bool(params.get("reframe_large_packets")),
+                    max_frame_len: params.get("max_frame_len")
+                        .and_then(|v| v.parse().ok())
+                        .map(|s: usize| s.clamp(64, 65536))
+                        .unwrap_or(1024),
                     is_primary_port: false,  // Will be set later
                     agw_port: params.get("agw_port")
                         .and_then(|v| v.parse().ok())
@@ -284,11 +409,14 @@ impl Config {
             }
         }
         
+        // Bridges are created, and their ports claimed, in cross_connectNNNN order
+        cross_connects.sort_by(|a, b| a.slot.cmp(&b.slot));
+        
         // Identify primary ports (KISS port 0 for each serial port)
         let mut serial_port_groups: HashMap<String, Vec<usize>> = HashMap::new();
         for (idx, cc) in cross_connects.iter().enumerate() {
             serial_port_groups.entry(cc.serial_port.clone())
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(idx);
         }
         
@@ -332,7 +460,7 @@ impl Config {
             .and_then(|v| v.parse::<usize>().ok())
             .unwrap_or(3);  // Default 3 AGW clients
         
-        Ok(Config {
+        Config {
             cross_connects,
             log_level,
             logfile,
@@ -346,155 +474,9 @@ impl Config {
             agw_server_address,
             agw_server_port,
             agw_max_clients,
-        })
-    }
-
-    fn apply_cli_overrides(&mut self, args: &[String]) {
-        let mut i = 1;
-        while i < args.len() {
-            match args[i].as_str() {
-                "-D" | "--device" if i + 1 < args.len() => {
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.serial_port = args[i + 1].clone();
-                    }
-                    i += 2;
-                }
-                "-b" | "--baud-rate" if i + 1 < args.len() => {
-                    if let Ok(rate) = args[i + 1].parse() {
-                        if let Some(cc) = self.cross_connects.get_mut(0) {
-                            cc.baud_rate = rate;
-                        }
-                    }
-                    i += 2;
-                }
-                "-s" | "--stop-bits" if i + 1 < args.len() => {
-                    let sb = match args[i + 1].as_str() {
-                        "1" => StopBits::One,
-                        "2" => StopBits::Two,
-                        _ => StopBits::One,
-                    };
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.stop_bits = sb;
-                    }
-                    i += 2;
-                }
-                "-Q" | "--parity" if i + 1 < args.len() => {
-                    let p = match args[i + 1].to_lowercase().as_str() {
-                        "n" | "none" => Parity::None,
-                        "e" | "even" => Parity::Even,
-                        "o" | "odd" => Parity::Odd,
-                        _ => Parity::None,
-                    };
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.parity = p;
-                    }
-                    i += 2;
-                }
-                "-x" | "--xon-xoff" => {
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.flow_control = FlowControl::Software;
-                    }
-                    i += 1;
-                }
-                "-H" | "--rts-cts" => {
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.flow_control = FlowControl::Hardware;
-                    }
-                    i += 1;
-                }
-                "--dtr-dsr" => {
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.flow_control = FlowControl::DtrDsr;
-                    }
-                    i += 1;
-                }
-                "-N" | "--none" => {
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.flow_control = FlowControl::None;
-                    }
-                    i += 1;
-                }
-                "-I" | "--address" if i + 1 < args.len() => {
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.tcp_address = args[i + 1].clone();
-                    }
-                    i += 2;
-                }
-                "-p" | "--port" if i + 1 < args.len() => {
-                    if let Ok(port) = args[i + 1].parse() {
-                        if let Some(cc) = self.cross_connects.get_mut(0) {
-                            cc.tcp_port = port;
-                        }
-                    }
-                    i += 2;
-                }
-                "-d" | "--dump" => {
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.dump_frames = true;
-                    }
-                    i += 1;
-                }
-                "-k" | "--kiss" => {
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.parse_kiss = true;
-                    }
-                    i += 1;
-                }
-                "-a" | "--ax25" => {
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.dump_ax25 = true;
-                    }
-                    i += 1;
-                }
-                "-n" | "--phil" => {
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.phil_flag = true;
-                    }
-                    i += 1;
-                }
-                "-R" | "--raw-copy" => {
-                    if let Some(cc) = self.cross_connects.get_mut(0) {
-                        cc.raw_copy = true;
-                    }
-                    i += 1;
-                }
-                "-l" | "--logfile" if i + 1 < args.len() => {
-                    self.logfile = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "-L" | "--log-level" if i + 1 < args.len() => {
-                    if let Ok(level) = args[i + 1].parse() {
-                        self.log_level = level;
-                    }
-                    i += 2;
-                }
-                "--console-only" => {
-                    self.log_to_file_only = false;
-                    self.log_to_console = true;
-                    i += 1;
-                }
-                "--no-console" => {
-                    self.log_to_console = false;
-                    self.log_to_file_only = true;
-                    i += 1;
-                }
-                "-P" | "--pidfile" if i + 1 < args.len() => {
-                    self.pidfile = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "--pcap" if i + 1 < args.len() => {
-                    self.pcap_file = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "-q" | "--quiet" => {
-                    self.quiet_startup = true;
-                    i += 1;
-                }
-                "-c" => {
-                    i += 2;
-                }
-                _ => i += 1,
-            }
+            secrets: entries.iter()
+                .filter_map(|entry| entry.secret.clone().map(|source| (entry.key.clone(), source)))
+                .collect(),
         }
     }
 }
@@ -510,7 +492,7 @@ fn parse_flow_control(opt: Option<&String>) -> FlowControl {
 }
 
 fn parse_stop_bits(opt: Option<&String>) -> StopBits {
-    opt.and_then(|v| match v.as_str() {
+    opt.and_then(|v| match v.to_lowercase().as_str() {
         "1" | "one" => Some(StopBits::One),
         "2" | "two" => Some(StopBits::Two),
         _ => None
@@ -527,7 +509,7 @@ fn parse_parity(opt: Option<&String>) -> Parity {
 }
 
 fn parse_data_bits(opt: Option<&String>) -> DataBits {
-    opt.and_then(|v| match v.as_str() {
+    opt.and_then(|v| match v.to_lowercase().as_str() {
         "7" | "seven" => Some(DataBits::Seven),
         "8" | "eight" => Some(DataBits::Eight),
         _ => None
@@ -543,31 +525,55 @@ fn parse_tcp_mode(opt: Option<&String>) -> TcpMode {
     }).unwrap_or(TcpMode::Server)
 }
 
-fn parse_bool(opt: Option<&String>) -> bool {
+fn parse_param_policy(opt: Option<&String>) -> ParamPolicy {
     opt.and_then(|v| match v.to_lowercase().as_str() {
-        "1" | "true" | "yes" => Some(true),
-        _ => Some(false)
-    }).unwrap_or(false)
+        "pass" => Some(ParamPolicy::Pass),
+        "override" => Some(ParamPolicy::Override),
+        "block" => Some(ParamPolicy::Block),
+        _ => None
+    }).unwrap_or(ParamPolicy::Pass)
 }
 
+fn parse_bool(opt: Option<&String>) -> bool {
+    opt.map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+// Settings can change while running (SIGHUP), hence the interior mutability
 struct Logger {
-    file: Option<Arc<Mutex<File>>>,
-    log_level: u8,
-    log_to_console: bool,
+    file: Mutex<Option<File>>,
+    log_level: AtomicU8,
+    log_to_console: AtomicBool,
 }
 
 impl Logger {
     fn new(logfile: Option<String>, log_level: u8, log_to_console: bool) -> Result<Self, Box<dyn std::error::Error>> {
-        let file = if let Some(path) = logfile {
-            Some(Arc::new(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?)))
-        } else {
-            None
-        };
-        Ok(Logger { file, log_level, log_to_console })
+        let file = logfile.map(Self::open_file).transpose()?;
+        Ok(Logger {
+            file: Mutex::new(file),
+            log_level: AtomicU8::new(log_level),
+            log_to_console: AtomicBool::new(log_to_console),
+        })
+    }
+
+    fn open_file(path: impl AsRef<std::path::Path>) -> std::io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    // Apply the log settings of a reloaded configuration. The log file is
+    // reopened even when its name is unchanged, so SIGHUP after log rotation
+    // starts writing the new file.
+    // If it cannot be opened the current file is kept.
+    fn reconfigure(&self, logfile: Option<&str>, log_level: u8, log_to_console: bool) -> std::io::Result<()> {
+        self.log_level.store(log_level, Ordering::SeqCst);
+        self.log_to_console.store(log_to_console, Ordering::SeqCst);
+        let file = logfile.map(Self::open_file).transpose()?;
+        *self.file.lock().unwrap() = file;
+        Ok(())
     }
 
     fn log(&self, message: &str, level: u8) {
-        if level > self.log_level { return; }
+        if level > self.log_level.load(Ordering::Relaxed) { return; }
         let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
         let level_str = match level {
             0 => "EMERG",
@@ -583,9 +589,9 @@ impl Logger {
             _ => "UNKNOWN",
         };
         let log_line = format!("[{}] [{}] {}", timestamp, level_str, message);
-        if self.log_to_console { println!("{}", log_line); }
-        if let Some(ref file) = self.file {
-            if let Ok(mut f) = file.lock() {
+        if self.log_to_console.load(Ordering::Relaxed) { println!("{}", log_line); }
+        if let Ok(mut file) = self.file.lock() {
+            if let Some(ref mut f) = *file {
                 let _ = writeln!(f, "{}", log_line);
             }
         }
@@ -631,8 +637,16 @@ impl PcapWriter {
         }
         Ok(())
     }
+
+    // Capture the AX.25 payload of a decoded KISS data frame; other commands are skipped
+    fn write_kiss_frame(&self, frame: &[u8]) {
+        if frame.len() > 1 && kiss::command(frame[0]) == 0 {
+            let _ = self.write_packet(&frame[1..]);
+        }
+    }
 }
 
+// Decoded frames from the TNC waiting for a host poll when emulating an XKISS TNC
 struct XkissRxBuffer {
     buffer: VecDeque<Vec<u8>>,
     max_size: usize,
@@ -659,21 +673,12 @@ impl XkissRxBuffer {
         Ok(())
     }
 
-    fn pop(&mut self) -> Option<Vec<u8>> {
-        if let Some(packet) = self.buffer.pop_front() {
-            self.current_size = self.current_size.saturating_sub(packet.len());
-            Some(packet)
-        } else {
-            None
-        }
-    }
-
-    fn poll_flush(&mut self) -> Vec<Vec<u8>> {
-        let mut packets = Vec::new();
-        while let Some(packet) = self.pop() {
-            packets.push(packet);
-        }
-        packets
+    // Oldest frame for XKISS address `port`
+    fn pop_for(&mut self, port: u8) -> Option<Vec<u8>> {
+        let position = self.buffer.iter().position(|packet| kiss::port(packet[0]) == port)?;
+        let packet = self.buffer.remove(position)?;
+        self.current_size = self.current_size.saturating_sub(packet.len());
+        Some(packet)
     }
 
     fn len(&self) -> usize {
@@ -692,12 +697,6 @@ impl XkissRxBuffer {
     }
 }
 
-struct TcpClientInfo {
-    stream: TcpStream,
-    #[allow(dead_code)]  // Used for connection tracking/debugging
-    connected_at: std::time::SystemTime,
-}
-
 // AGW (AGWPE) Protocol Structures
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
@@ -746,7 +745,7 @@ impl AgwHeader {
         Some(header)
     }
     
-    fn to_bytes(&self) -> Vec<u8> {
+    fn to_bytes(self) -> Vec<u8> {
         let mut buf = vec![0u8; Self::SIZE];
         buf[0..4].copy_from_slice(&self.port.to_le_bytes());
         buf[4..8].copy_from_slice(&self.reserved1.to_le_bytes());
@@ -769,605 +768,806 @@ struct AgwClientInfo {
     monitor_enabled: bool,             // Monitor mode flag
 }
 
-struct CrossConnectBridge {
-    config: CrossConnect,
-    serial_port: Arc<Mutex<Box<dyn SerialPort>>>,
-    tcp_clients: Arc<Mutex<Vec<Option<TcpClientInfo>>>>,  // Multiple clients
-    max_clients: usize,                                     // From global config
-    serial_peer: Option<Arc<Mutex<Box<dyn SerialPort>>>>,
-    xkiss_rx_buffer: Arc<Mutex<XkissRxBuffer>>,
-    logger: Arc<Logger>,
-    pcap_writer: Option<Arc<PcapWriter>>,
-    agw_clients: Arc<Mutex<Vec<Option<AgwClientInfo>>>>,  // AGW clients
-    agw_enabled: bool,                                      // AGW enabled for this bridge
+// Open a serial device with the line settings of a cross-connect. A USB or
+// by-id selector in serial_port is resolved first; the device path opened is
+// returned with the port.
+fn open_serial_port(config: &CrossConnect) -> Result<(Box<dyn SerialPort>, String), serialport::Error> {
+    let device = ports::resolve(&config.serial_port)
+        .map_err(|e| serialport::Error::new(serialport::ErrorKind::NoDevice, e))?;
+
+    // KISS specification requires 8N1 (8 data bits, no parity, 1 stop bit)
+    // When KISS or XKISS is enabled, enforce 8N1 regardless of config
+    let (data_bits, parity, stop_bits) = if !config.raw_copy {
+        // KISS/XKISS mode: force 8N1
+        (DataBits::Eight, Parity::None, StopBits::One)
+    } else {
+        // Raw copy mode: use configured values
+        (config.data_bits, config.parity, config.stop_bits)
+    };
+
+    let port = serialport::new(&device, config.baud_rate)
+        .timeout(Duration::from_millis(100))
+        .data_bits(match data_bits {
+            DataBits::Seven => serialport::DataBits::Seven,
+            DataBits::Eight => serialport::DataBits::Eight,
+        })
+        .stop_bits(match stop_bits {
+            StopBits::One => serialport::StopBits::One,
+            StopBits::Two => serialport::StopBits::Two,
+        })
+        .parity(match parity {
+            Parity::None => serialport::Parity::None,
+            Parity::Odd => serialport::Parity::Odd,
+            Parity::Even => serialport::Parity::Even,
+        })
+        .flow_control(match config.flow_control {
+            FlowControl::None => serialport::FlowControl::None,
+            FlowControl::Software => serialport::FlowControl::Software,
+            FlowControl::Hardware => serialport::FlowControl::Hardware,
+            FlowControl::DtrDsr => serialport::FlowControl::Hardware,
+        })
+        .open()?;
+    Ok((port, device))
 }
 
-impl CrossConnectBridge {
-    fn new(config: CrossConnect, max_clients: usize, 
-           shared_serial: Option<Arc<Mutex<Box<dyn SerialPort>>>>,
-           logger: Arc<Logger>, pcap_writer: Option<Arc<PcapWriter>>) 
-        -> Result<Self, Box<dyn std::error::Error>> {
-        
-        let serial_port = if config.is_primary_port {
-            // Primary port: Open new serial port with configured parameters
-            // KISS specification requires 8N1 (8 data bits, no parity, 1 stop bit)
-            // When KISS or XKISS is enabled, enforce 8N1 regardless of config
-            let (data_bits, parity, stop_bits) = if !config.raw_copy {
-                // KISS/XKISS mode: force 8N1
-                (DataBits::Eight, Parity::None, StopBits::One)
-            } else {
-                // Raw copy mode: use configured values
-                (config.data_bits, config.parity, config.stop_bits)
-            };
-            
-            let port = serialport::new(&config.serial_port, config.baud_rate)
-                .timeout(Duration::from_millis(100))
-                .data_bits(match data_bits {
-                    DataBits::Seven => serialport::DataBits::Seven,
-                    DataBits::Eight => serialport::DataBits::Eight,
-                })
-                .stop_bits(match stop_bits {
-                    StopBits::One => serialport::StopBits::One,
-                    StopBits::Two => serialport::StopBits::Two,
-                })
-                .parity(match parity {
-                    Parity::None => serialport::Parity::None,
-                    Parity::Odd => serialport::Parity::Odd,
-                    Parity::Even => serialport::Parity::Even,
-                })
-                .flow_control(match config.flow_control {
-                    FlowControl::None => serialport::FlowControl::None,
-                    FlowControl::Software => serialport::FlowControl::Software,
-                    FlowControl::Hardware => serialport::FlowControl::Hardware,
-                    FlowControl::DtrDsr => serialport::FlowControl::Hardware,
-                })
-                .open()?;
-            
-            Arc::new(Mutex::new(port))
-        } else {
-            // Secondary port: Use shared serial port from KISS port 0
-            shared_serial.expect("Secondary port requires shared serial handle")
+// Kinds of malformed frame counted per cross-connect
+#[derive(Debug, Clone, Copy)]
+enum FrameError {
+    Runt,           // Data frame too short for an AX.25 header
+    Oversize,       // Longer than max_frame_len
+    BadEscape,      // FESC not followed by TFEND/TFESC
+    BadChecksum,    // XKISS checksum mismatch
+    Resync,         // Bytes skipped to find the next FEND
+}
+
+// Malformed-frame counters for one cross-connect
+#[derive(Default)]
+struct FrameStats {
+    runts: AtomicU64,
+    oversize: AtomicU64,
+    bad_escapes: AtomicU64,
+    bad_checksums: AtomicU64,
+    resyncs: AtomicU64,
+}
+
+impl FrameStats {
+    // Count one malformed frame and log it with the running totals
+    fn record(&self, error: FrameError, bridge_id: &str, detail: &str, logger: &Logger) {
+        let counter = match error {
+            FrameError::Runt => &self.runts,
+            FrameError::Oversize => &self.oversize,
+            FrameError::BadEscape => &self.bad_escapes,
+            FrameError::BadChecksum => &self.bad_checksums,
+            FrameError::Resync => &self.resyncs,
         };
-        
-        let xkiss_buffer = XkissRxBuffer::new(config.xkiss_rx_buffer_size);
-        
-        // Initialize client vector with None values
-        let mut clients = Vec::with_capacity(max_clients);
-        for _ in 0..max_clients {
-            clients.push(None);
+        counter.fetch_add(1, Ordering::Relaxed);
+        logger.log(&format!("[{}] {} ({})", bridge_id, detail, self.summary()), 4);
+    }
+
+    fn summary(&self) -> String {
+        format!("runts={} oversize={} bad_escapes={} bad_checksums={} resyncs={}",
+            self.runts.load(Ordering::Relaxed),
+            self.oversize.load(Ordering::Relaxed),
+            self.bad_escapes.load(Ordering::Relaxed),
+            self.bad_checksums.load(Ordering::Relaxed),
+            self.resyncs.load(Ordering::Relaxed))
+    }
+}
+
+// One cross-connect registered on a serial demultiplexer
+struct DemuxRoute {
+    id: String,
+    owner: String,                    // Slot of the bridge that registered it
+    kiss_port: u8,
+    is_primary: bool,
+    sender: mpsc::Sender<Vec<u8>>,
+    stats: Arc<FrameStats>,
+    poll_timeout: Option<Duration>,   // Set when this XKISS address is polled
+    checksum: bool,                   // XKISS checksum on frames to this address
+    params: Option<ManagedParams>,    // KISS parameters kept set on this port
+}
+
+// The managed KISS parameters of a route, ready for the line
+struct ManagedParams {
+    frames: Vec<u8>,                  // One encoded frame per parameter
+    summary: String,                  // "TXDELAY 30, PERSIST 63" for the log
+    interval: Option<Duration>,       // Re-sent this often
+    sent: Instant,
+}
+
+impl ManagedParams {
+    fn new(config: &CrossConnect) -> Option<Self> {
+        let params = config.kiss_params();
+        if params.is_empty() {
+            return None;
         }
-        
-        let agw_enabled = config.agw_enable;  // Save before moving config
-        
-        Ok(CrossConnectBridge {
-            config,
-            serial_port,
-            tcp_clients: Arc::new(Mutex::new(clients)),
-            max_clients,
-            serial_peer: None,
-            xkiss_rx_buffer: Arc::new(Mutex::new(xkiss_buffer)),
-            logger,
-            pcap_writer,
-            agw_clients: Arc::new(Mutex::new((0..max_clients).map(|_| None).collect())),
-            agw_enabled,
+        let port = config.wire_port() << 4;
+        Some(ManagedParams {
+            frames: params.iter()
+                .flat_map(|&(command, value)| encode_for_serial(&[port | command, value], config))
+                .collect(),
+            summary: params.iter()
+                .map(|&(command, value)| format!("{} {}", kiss::param_name(command).unwrap_or("?"), value))
+                .collect::<Vec<_>>()
+                .join(", "),
+            interval: (config.kiss_param_interval > 0).then(|| Duration::from_secs(config.kiss_param_interval)),
+            sent: Instant::now(),
         })
     }
+}
+
+// Frames held per XKISS address before it is polled
+const MAX_HELD_FRAMES: usize = 64;
+
+// Run once a frame has left the serial line (used for emulated ACKMODE acks)
+type DrainHook = Box<dyn FnOnce() + Send>;
+
+// Encoded frame waiting for its address to be polled
+type HeldFrame = (Vec<u8>, Option<DrainHook>);
+
+// Frames waiting for a device's writer thread
+const WRITE_QUEUE_LEN: usize = 256;
+
+// Work for a device's writer thread, done in order
+enum WriteJob {
+    Frame(Vec<u8>, Option<DrainHook>),
+    Close,                                  // Run the exit script and stop
+}
+
+// Tracks when the serial line finishes sending what has been written to it
+struct LineClock {
+    byte_time: Duration,
+    free_at: Mutex<Instant>,
+}
+
+impl LineClock {
+    fn new(baud_rate: u32) -> Self {
+        // KISS lines are 8N1: 10 bits per byte
+        LineClock {
+            byte_time: Duration::from_secs_f64(10.0 / baud_rate.max(1) as f64),
+            free_at: Mutex::new(Instant::now()),
+        }
+    }
 
-    #[allow(dead_code)] // API method for serial-to-serial peer setup
-    fn set_serial_peer(&mut self, peer_port: Arc<Mutex<Box<dyn SerialPort>>>) {
-        self.serial_peer = Some(peer_port);
+    // Account for `len` bytes just written; returns when they will have left the line
+    fn reserve(&self, len: usize) -> Instant {
+        let mut free_at = self.free_at.lock().unwrap();
+        *free_at = (*free_at).max(Instant::now()) + self.byte_time * len as u32;
+        *free_at
     }
+}
+
+// Address currently being polled and whether the TNC has answered yet
+struct PollSlot {
+    address: u8,
+    answered: bool,
+}
+
+// Polled XKISS bus state shared by the poll master, the reader and writers
+#[derive(Default)]
+struct PollBus {
+    active: AtomicBool,                 // Poll master running on this device
+    current: Mutex<Option<PollSlot>>,
+    response: Condvar,
+    held: Mutex<HashMap<u8, VecDeque<HeldFrame>>>,
+}
+
+// Whether a serial device can be used
+#[derive(Debug, Clone, PartialEq)]
+enum DeviceState {
+    Open,
+    Lost { since: Instant, error: String },   // Failed or unplugged; being reopened
+    Closed,                                   // Released by close()
+}
 
-    #[allow(dead_code)] // Instance method kept for potential future use
-    fn translate_kiss_port(&self, data: &[u8], from_xkiss: bool, to_xkiss: bool) -> Vec<u8> {
-        if data.is_empty() || (!from_xkiss && !to_xkiss) {
-            return data.to_vec();
+impl DeviceState {
+    // Mark the device lost after a fatal error; the reader thread reopens it
+    fn lose(&mut self, error: &std::io::Error) {
+        if *self == DeviceState::Open {
+            *self = DeviceState::Lost { since: Instant::now(), error: error.to_string() };
         }
+    }
+}
 
-        let mut result = Vec::with_capacity(data.len());
-        
-        if from_xkiss && !to_xkiss {
-            if let Some(_xkiss_port) = self.config.xkiss_port {
-                for &byte in data {
-                    if byte == KISS_FEND {
-                        result.push(byte);
-                    } else if result.len() == 1 {
-                        let cmd_type = byte & 0x0F;
-                        let new_byte = (self.config.kiss_port << 4) | cmd_type;
-                        result.push(new_byte);
-                    } else {
-                        result.push(byte);
-                    }
-                }
-            } else {
-                result = data.to_vec();
-            }
-        } else if !from_xkiss && to_xkiss {
-            if let Some(xkiss_port) = self.config.xkiss_port {
-                for &byte in data {
-                    if byte == KISS_FEND {
-                        result.push(byte);
-                    } else if result.len() == 1 {
-                        let cmd_type = byte & 0x0F;
-                        let new_byte = (xkiss_port << 4) | cmd_type;
-                        result.push(new_byte);
-                    } else {
-                        result.push(byte);
-                    }
-                }
-            } else {
-                result = data.to_vec();
-            }
-        } else {
-            result = data.to_vec();
+// The writer's half of a serial device's port, present while it is open
+type WriterHalf = Arc<Mutex<Option<Box<dyn SerialPort>>>>;
+
+// Read timeouts are normal on an idle line; any other error means the device
+// is gone or broken (EIO, broken pipe, device not configured, ...)
+fn is_fatal(error: &std::io::Error) -> bool {
+    !matches!(error.kind(),
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted | std::io::ErrorKind::WouldBlock)
+}
+
+// Serial demultiplexer: the single reader and writer for one physical device.
+//
+// Multi-port TNCs (KPC-9612, KAM, ...) carry several KISS ports on one serial
+// line. All cross-connects on that line share one SerialDemux. Its reader
+// thread deframes KISS and hands each frame to the cross-connect whose wire
+// port (kiss_port, or xkiss_port in XKISS mode) matches the port nibble of the
+// command byte; frames for a port nobody registered go to the primary port.
+// All transmit traffic goes through write() so frames from different ports
+// never interleave on the wire.
+//
+// The port is split with try_clone: the reader thread owns one half, and a
+// writer thread owns the other and writes what write() queues, in order. A
+// frame to send never waits for a read to time out on a quiet line.
+//
+// When a cross-connect on the device sets xkiss_polling, the demux is also the
+// G8BPQ poll master for the bus: it polls each polled XKISS address in turn,
+// waits for data or the poll echo, and holds frames for an address until that
+// address has been polled.
+//
+// A fatal read or write error (a USB adapter unplugged, say) drops the port.
+// The reader thread then reopens the device with the settings it was first
+// opened with, backing off up to a minute between attempts. Routes stay
+// registered meanwhile, so the cross-connects and their clients are not
+// disturbed; writes fail until the device is back.
+struct SerialDemux {
+    device: String,
+    settings: CrossConnect,                 // Line settings for reopening
+    state: Arc<Mutex<DeviceState>>,
+    writer: WriterHalf,
+    queue: mpsc::SyncSender<WriteJob>,      // To the writer thread
+    routes: Arc<Mutex<Vec<DemuxRoute>>>,
+    raw_copy: bool,
+    max_frame_len: usize,
+    bus: Arc<PollBus>,
+    logger: Arc<Logger>,
+    reader_thread: Mutex<Option<thread::JoinHandle<()>>>,
+    writer_thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl SerialDemux {
+    // Framing options come from the cross-connect that opened the device
+    fn new(config: &CrossConnect, port: Box<dyn SerialPort>, logger: Arc<Logger>)
+        -> serialport::Result<Arc<Self>> {
+        let writer = port.try_clone()?;
+        let (queue, jobs) = mpsc::sync_channel(WRITE_QUEUE_LEN);
+        let demux = Arc::new(SerialDemux {
+            device: config.serial_port.clone(),
+            settings: config.clone(),
+            state: Arc::new(Mutex::new(DeviceState::Open)),
+            writer: Arc::new(Mutex::new(Some(writer))),
+            queue,
+            routes: Arc::new(Mutex::new(Vec::new())),
+            raw_copy: config.raw_copy,
+            max_frame_len: config.max_frame_len,
+            bus: Arc::new(PollBus::default()),
+            logger,
+            reader_thread: Mutex::new(None),
+            writer_thread: Mutex::new(None),
+        });
+        *demux.writer_thread.lock().unwrap() = Some(demux.start_writer(jobs, LineClock::new(config.baud_rate)));
+        *demux.reader_thread.lock().unwrap() = Some(demux.start_reader(port));
+        Ok(demux)
+    }
+
+    // Register a cross-connect for the bridge `owner`; returns the receiving end for its frames.
+    // Each item is the frame content between FENDs, or a raw chunk in raw_copy mode.
+    // Framing errors on this cross-connect's port are counted in `stats`.
+    fn register(&self, owner: &str, config: &CrossConnect, stats: Arc<FrameStats>) -> mpsc::Receiver<Vec<u8>> {
+        let (sender, receiver) = mpsc::channel();
+        let poll_timeout = (config.xkiss_mode && config.xkiss_polling)
+            .then(|| Duration::from_millis(config.xkiss_poll_timer_ms));
+        self.routes.lock().unwrap().push(DemuxRoute {
+            id: config.id.clone(),
+            owner: owner.to_string(),
+            kiss_port: config.wire_port(),
+            is_primary: config.is_primary_port,
+            sender,
+            stats,
+            poll_timeout,
+            checksum: config.xkiss_mode && config.xkiss_checksum,
+            params: if self.raw_copy { None } else { ManagedParams::new(config) },
+        });
+        self.logger.log(&format!("[{}] Registered KISS port {} on {}",
+            config.id, config.wire_port(), self.device), 7);
+        Self::send_params(&self.state, &self.queue, &self.routes, &self.device,
+            |route| route.owner == owner && route.kiss_port == config.wire_port(), 6, &self.logger);
+        if poll_timeout.is_some() && !self.raw_copy && !self.bus.active.swap(true, Ordering::SeqCst) {
+            self.start_poller();
         }
-        
-        result
+        receiver
     }
 
-    fn start_tcp_client(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let server_addr = self.config.tcp_server_address.as_ref()
-            .ok_or("Missing tcp_server_address for client mode")?;
-        let server_port = self.config.tcp_server_port
-            .ok_or("Missing tcp_server_port for client mode")?;
-        let connect_address = format!("{}:{}", server_addr, server_port);
-        
-        self.logger.log(&format!("[{}] TCP client connecting to {}", 
-            self.config.id, connect_address), 5);
-        
-        let tcp_clients = self.tcp_clients.clone();
-        let serial = self.serial_port.clone();
-        let serial_peer = self.serial_peer.clone();
-        let config = self.config.clone();
+    // Remove the routes registered by bridge `owner`; their receivers see the channel close
+    fn unregister(&self, owner: &str) {
+        self.routes.lock().unwrap().retain(|route| route.owner != owner);
+    }
+
+    // Release the device. The reader and poll master stop, every route is
+    // closed and later writes fail. Frames already queued are written, then
+    // the exit script, if any.
+    fn close(&self) {
+        *self.state.lock().unwrap() = DeviceState::Closed;
+        self.routes.lock().unwrap().clear();
+        // The reader stops first so it cannot take the exit script's replies
+        if let Some(reader) = self.reader_thread.lock().unwrap().take() {
+            let _ = reader.join();
+        }
+        if let Some(writer) = self.writer_thread.lock().unwrap().take() {
+            let _ = self.queue.send(WriteJob::Close);
+            let _ = writer.join();
+        }
+        self.logger.log(&format!("[{}] Serial device closed", self.device), 6);
+    }
+
+    fn state(&self) -> DeviceState {
+        self.state.lock().unwrap().clone()
+    }
+
+    // Shared writer for every cross-connect on this device
+    fn write(&self, data: &[u8]) -> std::io::Result<()> {
+        Self::transmit(&self.state, &self.queue, data, None)
+    }
+
+    // Queue `data` for the writer thread, which runs `on_drained` once the
+    // bytes have been written and drained at line rate
+    fn transmit(state: &Mutex<DeviceState>, queue: &mpsc::SyncSender<WriteJob>, data: &[u8],
+                on_drained: Option<DrainHook>) -> std::io::Result<()> {
+        match *state.lock().map_err(|_| std::io::Error::other("serial state lock poisoned"))? {
+            DeviceState::Open => {}
+            DeviceState::Lost { .. } => return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected, "serial device unavailable, reopening")),
+            DeviceState::Closed => return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected, "serial device closed")),
+        }
+        queue.try_send(WriteJob::Frame(data.to_vec(), on_drained)).map_err(|e| match e {
+            mpsc::TrySendError::Full(_) => std::io::Error::other(format!(
+                "serial write queue full ({} frames)", WRITE_QUEUE_LEN)),
+            mpsc::TrySendError::Disconnected(_) => std::io::Error::new(
+                std::io::ErrorKind::NotConnected, "serial device closed"),
+        })
+    }
+
+    // Writer thread: write queued frames to the writer's half of the port
+    // until close(). Drain hooks run on a second thread, in order, once the
+    // line clock says their frame has left.
+    fn start_writer(&self, jobs: mpsc::Receiver<WriteJob>, line: LineClock) -> thread::JoinHandle<()> {
+        let settings = self.settings.clone();
+        let state = self.state.clone();
+        let writer = self.writer.clone();
+        let device = self.device.clone();
         let logger = self.logger.clone();
-        let connect_addr_clone = connect_address.clone();
-        
+
+        let (drains, hooks) = mpsc::channel::<(Instant, DrainHook)>();
         thread::spawn(move || {
-            let mut reconnect_delay = Duration::from_secs(1);
-            let max_reconnect_delay = Duration::from_secs(60);
-            
-            loop {
-                match TcpStream::connect(&connect_addr_clone) {
-                    Ok(mut stream) => {
-                        logger.log(&format!("[{}] Connected to {}", config.id, connect_addr_clone), 5);
-                        reconnect_delay = Duration::from_secs(1);
-                        
-                        {
-                            let mut clients = tcp_clients.lock().unwrap();
-                            clients[0] = Some(TcpClientInfo {
-                                stream: stream.try_clone().unwrap(),
-                                connected_at: std::time::SystemTime::now(),
-                            });
+            for (drained, hook) in hooks {
+                thread::sleep(drained.saturating_duration_since(Instant::now()));
+                hook();
+            }
+        });
+
+        thread::spawn(move || {
+            for job in jobs {
+                let (data, on_drained) = match job {
+                    WriteJob::Frame(data, on_drained) => (data, on_drained),
+                    WriteJob::Close => {
+                        let port = writer.lock().unwrap().take();
+                        if let (Some(mut port), Some(ref exit_script)) = (port, &settings.exit_script) {
+                            if let Err(e) = script::run("exit_script", exit_script, &device, port.as_mut(), &logger) {
+                                logger.log(&format!("[{}] exit_script failed: {}", device, e), 4);
+                            }
                         }
-                        
-                        let tcp_clients_clone = tcp_clients.clone();
-                        let serial_clone = serial.clone();
-                        let serial_peer_clone = serial_peer.clone();
-                        let config_clone = config.clone();
-                        let logger_clone = logger.clone();
-                        
-                        let mut buffer = vec![0u8; 4096];
-                        loop {
-                            match stream.read(&mut buffer) {
-                                Ok(0) => {
-                                    logger_clone.log(&format!("[{}] Server disconnected", config_clone.id), 6);
-                                    let mut clients = tcp_clients_clone.lock().unwrap();
-                                    clients[0] = None;
-                                    break;
-                                }
-                                Ok(n) => {
-                                    let data = &buffer[..n];
-                                    
-                                    if config_clone.tcp_to_tcp_dangerous {
-                                        if !config_clone.tcp_to_tcp_also_dangerous && !is_kiss_packet(data) {
-                                            logger_clone.log(&format!("[{}] Non-KISS packet rejected in TCP-to-TCP mode", 
-                                                config_clone.id), 4);
-                                            continue;
-                                        }
-                                    }
-                                    
-                                    if config_clone.dump_frames {
-                                        logger_clone.log(&format!("[{}] TCP->Serial ({} bytes): {:02x?}", 
-                                            config_clone.id, n, data), 7);
-                                    }
-                                    
-                                    let processed = if config_clone.raw_copy {
-                                        data.to_vec()
-                                    } else if config_clone.phil_flag {
-                                        process_phil_flag_tcp_to_serial(data)
-                                    } else {
-                                        data.to_vec()
-                                    };
-                                    
-                                    if let Some(ref peer) = serial_peer_clone {
-                                        let translated = Self::translate_kiss_port_static(
-                                            &processed, &config_clone, false, config_clone.xkiss_mode);
-                                        if let Ok(mut port) = peer.lock() {
-                                            let _ = port.write_all(&translated);
-                                        }
-                                    } else {
-                                        if let Ok(mut port) = serial_clone.lock() {
-                                            let _ = port.write_all(&processed);
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    if e.kind() != std::io::ErrorKind::WouldBlock {
-                                        logger_clone.log(&format!("[{}] TCP read error: {}", config_clone.id, e), 4);
-                                        let mut clients = tcp_clients_clone.lock().unwrap();
-                                        clients[0] = None;
-                                        break;
-                                    }
+                        break;
+                    }
+                };
+                let written = {
+                    let mut port = writer.lock().unwrap();
+                    match port.as_mut() {
+                        Some(serial) => {
+                            let written = serial.write_all(&data);
+                            if let Err(ref e) = written {
+                                if is_fatal(e) {
+                                    *port = None;
+                                    state.lock().unwrap().lose(e);
                                 }
                             }
-                            thread::sleep(Duration::from_millis(10));
+                            written
                         }
+                        // Lost since the frame was queued
+                        None => Err(std::io::Error::new(
+                            std::io::ErrorKind::NotConnected, "serial device unavailable, reopening")),
                     }
-                    Err(e) => {
-                        logger.log(&format!("[{}] Connection failed: {} - retrying in {:?}", 
-                            config.id, e, reconnect_delay), 4);
-                        thread::sleep(reconnect_delay);
-                        reconnect_delay = (reconnect_delay * 2).min(max_reconnect_delay);
-                    }
+                };
+                if let Err(e) = written {
+                    logger.log(&format!("[{}] Serial write error, {} bytes dropped: {}",
+                        device, data.len(), e), 4);
+                    continue;
+                }
+                let drained = line.reserve(data.len());
+                if let Some(hook) = on_drained {
+                    let _ = drains.send((drained, hook));
                 }
             }
-        });
-        
+        })
+    }
+
+    // Write one encoded frame addressed to KISS/XKISS `port`. If that address
+    // is polled, the frame is held until the poll master has polled it.
+    // `on_drained` runs once the frame has actually been sent.
+    fn write_frame(&self, port: u8, data: &[u8], on_drained: Option<DrainHook>) -> std::io::Result<()> {
+        let polled = self.routes.lock().unwrap().iter()
+            .any(|route| route.kiss_port == port && route.poll_timeout.is_some());
+        if !polled {
+            return Self::transmit(&self.state, &self.queue, data, on_drained);
+        }
+        let mut held = self.bus.held.lock().unwrap();
+        let queue = held.entry(port).or_default();
+        if queue.len() >= MAX_HELD_FRAMES {
+            return Err(std::io::Error::other(format!(
+                "{} frames already held for XKISS address {}", queue.len(), port)));
+        }
+        queue.push_back((data.to_vec(), on_drained));
         Ok(())
     }
 
-    fn start_tcp_listener(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let bind_address = format!("{}:{}", self.config.tcp_address, self.config.tcp_port);
-        let listener = TcpListener::bind(&bind_address)?;
-        self.logger.log(&format!("[{}] TCP listener on {} (max {} clients)", 
-            self.config.id, bind_address, self.max_clients), 5);
-        
-        let tcp_clients = self.tcp_clients.clone();
-        let max_clients = self.max_clients;
-        let serial = self.serial_port.clone();
-        let serial_peer = self.serial_peer.clone();
-        let config = self.config.clone();
+    // Poll master: poll every polled address in turn, wait for its answer,
+    // then release the frames held for it
+    fn start_poller(&self) {
+        let state = self.state.clone();
+        let queue = self.queue.clone();
+        let routes = self.routes.clone();
+        let bus = self.bus.clone();
+        let device = self.device.clone();
         let logger = self.logger.clone();
-        
-        // Accept loop thread
-        thread::spawn(move || {
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(client_stream) => {
-                        let peer_addr = client_stream.peer_addr().ok();
-                        let mut clients = tcp_clients.lock().unwrap();
-                        
-                        // Find empty slot
-                        let slot = clients.iter_mut()
-                            .enumerate()
-                            .find(|(_, c)| c.is_none());
-                        
-                        if let Some((index, slot_ref)) = slot {
-                            let info = TcpClientInfo {
-                                stream: client_stream.try_clone().unwrap(),
-                                connected_at: std::time::SystemTime::now(),
-                            };
-                            *slot_ref = Some(info);
-                            drop(clients);  // Release lock before spawning thread
-                            
-                            logger.log(&format!("[{}] Client {} connected from {:?}", 
-                                config.id, index, peer_addr), 6);
-                            
-                            // Spawn read thread for this client
-                            let tcp_clients_clone = tcp_clients.clone();
-                            let serial_clone = serial.clone();
-                            let serial_peer_clone = serial_peer.clone();
-                            let config_clone = config.clone();
-                            let logger_clone = logger.clone();
-                            
-                            thread::spawn(move || {
-                                let mut buffer = vec![0u8; 4096];
-                                let mut client_stream = client_stream;
-                                
-                                loop {
-                                    match client_stream.read(&mut buffer) {
-                                        Ok(0) => {
-                                            logger_clone.log(&format!("[{}] Client {} disconnected", 
-                                                config_clone.id, index), 6);
-                                            let mut clients = tcp_clients_clone.lock().unwrap();
-                                            clients[index] = None;
-                                            break;
-                                        }
-                                        Ok(n) => {
-                                            let data = &buffer[..n];
-                                            
-                                            if config_clone.dump_frames {
-                                                logger_clone.log(&format!("[{}] Client {}->Serial ({} bytes): {:02x?}", 
-                                                    config_clone.id, index, n, data), 7);
-                                            }
-                                            
-                                            // Apply channel remapping if needed (TCP -> Serial)
-                                            let processed = if config_clone.kiss_chan >= 0 && config_clone.kiss_chan <= 15 {
-                                                // Remap KISS channel 0 back to configured channel
-                                                Self::remap_kiss_channel_in(data, config_clone.kiss_port)
-                                            } else if config_clone.raw_copy {
-                                                data.to_vec()
-                                            } else if config_clone.phil_flag {
-                                                process_phil_flag_tcp_to_serial(data)
-                                            } else {
-                                                data.to_vec()
-                                            };
-                                            
-                                            // KISSCOPY: Send to other clients
-                                            if config_clone.kiss_copy {
-                                                Self::broadcast_to_other_clients(
-                                                    index, &processed, &tcp_clients_clone, &config_clone, &logger_clone
-                                                );
-                                            }
-                                            
-                                            // Send to serial
-                                            if let Some(ref peer) = serial_peer_clone {
-                                                let translated = Self::translate_kiss_port_static(
-                                                    &processed, &config_clone, false, config_clone.xkiss_mode);
-                                                if let Ok(mut port) = peer.lock() {
-                                                    let _ = port.write_all(&translated);
-                                                }
-                                            } else {
-                                                if let Ok(mut port) = serial_clone.lock() {
-                                                    let _ = port.write_all(&processed);
-                                                }
-                                            }
-                                        }
-                                        Err(e) => {
-                                            if e.kind() != std::io::ErrorKind::WouldBlock {
-                                                logger_clone.log(&format!("[{}] Client {} read error: {}", 
-                                                    config_clone.id, index, e), 4);
-                                                let mut clients = tcp_clients_clone.lock().unwrap();
-                                                clients[index] = None;
-                                                break;
-                                            }
-                                        }
-                                    }
-                                    thread::sleep(Duration::from_millis(10));
-                                }
-                            });
-                        } else {
-                            drop(clients);
-                            logger.log(&format!("[{}] Connection refused from {:?} - {} clients already connected", 
-                                config.id, peer_addr, max_clients), 4);
-                        }
-                    }
-                    Err(e) => {
-                        logger.log(&format!("[{}] Accept error: {}", config.id, e), 3);
+
+        logger.log(&format!("[{}] XKISS poll master started", device), 6);
+
+        thread::spawn(move || loop {
+            match *state.lock().unwrap() {
+                DeviceState::Open => {}
+                DeviceState::Lost { .. } => {
+                    thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+                DeviceState::Closed => break,
+            }
+            let mut targets: Vec<(u8, Duration, bool)> = routes.lock().unwrap().iter()
+                .filter_map(|route| route.poll_timeout.map(|timeout| (route.kiss_port, timeout, route.checksum)))
+                .collect();
+            targets.sort_by_key(|&(address, _, _)| address);
+            targets.dedup_by_key(|&mut (address, _, _)| address);
+
+            for (address, timeout, checksum) in targets {
+                let mut poll = vec![(address << 4) | kiss::CMD_POLL];
+                if checksum {
+                    poll.push(calculate_xkiss_checksum(&poll));
+                }
+
+                *bus.current.lock().unwrap() = Some(PollSlot { address, answered: false });
+                if let Err(e) = Self::transmit(&state, &queue, &kiss::encode(&poll), None) {
+                    logger.log(&format!("[{}] Serial write error polling address {}: {}",
+                        device, address, e), 4);
+                }
+
+                // Wait for a data frame or the poll echo from this address
+                let current = bus.current.lock().unwrap();
+                let (mut current, _) = bus.response.wait_timeout_while(current, timeout,
+                    |slot| !slot.as_ref().is_some_and(|slot| slot.answered)).unwrap();
+                if !current.as_ref().is_some_and(|slot| slot.answered) {
+                    logger.log(&format!("[{}] No answer to poll from XKISS address {}",
+                        device, address), 8);
+                }
+                *current = None;
+                drop(current);
+
+                // The TNC has been polled: release what was held for it
+                let held = bus.held.lock().unwrap().remove(&address).unwrap_or_default();
+                for (frame, on_drained) in held {
+                    if let Err(e) = Self::transmit(&state, &queue, &frame, on_drained) {
+                        logger.log(&format!("[{}] Serial write error to address {}: {}",
+                            device, address, e), 4);
                     }
                 }
             }
+            thread::sleep(Duration::from_millis(10));
         });
-        
-        // Serial read thread (broadcasts to all connected TCP clients)
-        let serial = self.serial_port.clone();
-        let _serial_peer = self.serial_peer.clone();
-        let tcp_clients = self.tcp_clients.clone();
-        let agw_clients = self.agw_clients.clone();
-        let agw_enabled = self.agw_enabled;
-        let xkiss_buffer = self.xkiss_rx_buffer.clone();
-        let config = self.config.clone();
+    }
+
+    // Send the managed KISS parameters of the routes `pick` selects, and log
+    // it at `level`
+    fn send_params(state: &Mutex<DeviceState>, queue: &mpsc::SyncSender<WriteJob>,
+                   routes: &Mutex<Vec<DemuxRoute>>, device: &str, pick: impl Fn(&DemuxRoute) -> bool,
+                   level: u8, logger: &Logger) {
+        let due: Vec<(String, Vec<u8>, String)> = routes.lock().unwrap().iter_mut()
+            .filter(|route| pick(route))
+            .filter_map(|route| {
+                let params = route.params.as_mut()?;
+                params.sent = Instant::now();
+                Some((route.id.clone(), params.frames.clone(), params.summary.clone()))
+            })
+            .collect();
+        for (id, frames, summary) in due {
+            match Self::transmit(state, queue, &frames, None) {
+                Ok(()) => logger.log(&format!("[{}] KISS parameters sent to {}: {}", id, device, summary), level),
+                Err(e) => logger.log(&format!("[{}] Cannot send KISS parameters to {}: {}", id, device, e), 4),
+            }
+        }
+    }
+
+    // Note a TNC's answer to the current poll. Returns true for poll frames,
+    // which are bus traffic and are not routed to any cross-connect.
+    fn poll_response(bus: &PollBus, frame: &[u8]) -> bool {
+        if !bus.active.load(Ordering::SeqCst) {
+            return false;
+        }
+        let Some(command_byte) = kiss::command_byte(frame) else { return false };
+        let mut current = bus.current.lock().unwrap();
+        if let Some(slot) = current.as_mut() {
+            if slot.address == kiss::port(command_byte) {
+                slot.answered = true;
+                bus.response.notify_all();
+            }
+        }
+        kiss::command(command_byte) == kiss::CMD_POLL
+    }
+
+    // Reader thread: owns the reader's half of the port, deframes and routes
+    // what it reads, and reopens the device when it is lost
+    fn start_reader(&self, port: Box<dyn SerialPort>) -> thread::JoinHandle<()> {
+        let settings = self.settings.clone();
+        let state = self.state.clone();
+        let writer = self.writer.clone();
+        let queue = self.queue.clone();
+        let routes = self.routes.clone();
+        let device = self.device.clone();
+        let raw_copy = self.raw_copy;
+        let max_frame_len = self.max_frame_len;
+        let bus = self.bus.clone();
         let logger = self.logger.clone();
-        let pcap = self.pcap_writer.clone();
-        
-        thread::spawn(move || {
-            let mut buffer = vec![0u8; 4096];
-            let mut frame_buffer = Vec::new();
-            let mut in_frame = false;
-            
-            loop {
-                if let Ok(mut port) = serial.lock() {
-                    match port.read(&mut buffer) {
-                        Ok(n) if n > 0 => {
-                            let data = &buffer[..n];
-                            
-                            if config.raw_copy {
-                                // Raw copy mode: send to all clients
-                                let clients = tcp_clients.lock().unwrap();
-                                for (i, client) in clients.iter().enumerate() {
-                                    if let Some(info) = client {
-                                        if let Ok(mut stream) = info.stream.try_clone() {
-                                            if let Err(e) = stream.write_all(data) {
-                                                logger.log(&format!("[{}] Client {} write error: {}", 
-                                                    config.id, i, e), 4);
-                                            }
-                                        }
-                                    }
-                                }
-                                continue;
-                            }
-                            
-                            for &byte in data {
-                                if byte == KISS_FEND {
-                                    if in_frame && !frame_buffer.is_empty() {
-                                        let processed = if config.phil_flag {
-                                            process_frame_with_phil_flag(&frame_buffer)
-                                        } else {
-                                            frame_buffer.clone()
-                                        };
-                                        
-                                        if config.dump_frames {
-                                            logger.log(&format!("[{}] Serial->Out ({} bytes): {:02x?}", 
-                                                config.id, processed.len(), processed), 7);
-                                        }
-                                        
-                                        if config.parse_kiss {
-                                            parse_kiss_frame(&processed, &logger, &config.id);
-                                        }
-                                        
-                                        if let Some(ref pcap) = pcap {
-                                            if processed.len() > 1 {
-                                                let _ = pcap.write_packet(&processed[1..]);
-                                            }
-                                        }
-                                        
-                                        let mut full_frame = vec![KISS_FEND];
-                                        full_frame.extend_from_slice(&processed);
-                                        full_frame.push(KISS_FEND);
-                                        
-                                        // Check channel filtering and apply channel remapping
-                                        let should_send = if config.kiss_chan == -1 {
-                                            true  // All channels
-                                        } else if processed.len() > 0 {
-                                            let kiss_byte = processed[0];
-                                            let channel = (kiss_byte >> 4) & 0x0F;
-                                            channel as i32 == config.kiss_chan
-                                        } else {
-                                            false
-                                        };
-                                        
-                                        if should_send {
-                                            // Apply channel remapping if needed (Serial -> TCP)
-                                            let final_frame = if config.kiss_chan >= 0 && config.kiss_chan <= 15 && processed.len() > 0 {
-                                                // Remap channel to 0 for application
-                                                let mut remapped = full_frame.clone();
-                                                let kiss_byte = remapped[1];
-                                                let cmd = kiss_byte & 0x0F;
-                                                remapped[1] = (0 << 4) | cmd;  // Channel 0
-                                                remapped
-                                            } else {
-                                                full_frame.clone()
-                                            };
-                                            
-                                            // If XKISS polling is enabled, buffer the packet
-                                            if config.xkiss_mode && config.xkiss_polling {
-                                                if let Ok(mut buf) = xkiss_buffer.lock() {
-                                                    match buf.push(final_frame.clone()) {
-                                                        Ok(_) => {
-                                                            if config.dump_frames {
-                                                                logger.log(&format!("[{}] Buffered packet ({} bytes, buffer has {})", 
-                                                                    config.id, final_frame.len(), buf.len()), 7);
-                                                            }
-                                                        }
-                                                        Err(e) => {
-                                                            logger.log(&format!("[{}] Buffer full, dropping packet: {}", 
-                                                                config.id, e), 4);
-                                                        }
-                                                    }
-                                                }
-                                            } else {
-                                                // Send immediately to all TCP clients
-                                                Self::send_to_all_tcp_clients(&final_frame, &tcp_clients, &config, &logger);
-                                                
-                                                // Also send to AGW clients if enabled
-                                                if agw_enabled {
-                                                    send_to_agw_clients(&final_frame, &agw_clients, 
-                                                        config.agw_port, false, &logger, &config.id);
-                                                }
-                                            }
-                                        }
-                                        
-                                        frame_buffer.clear();
+
+        thread::spawn(move || {
+            let mut buffer = vec![0u8; 4096];
+            let mut deframer = kiss::Deframer::new(max_frame_len);
+            let mut port = Some(port);
+
+            loop {
+                match *state.lock().unwrap() {
+                    DeviceState::Open => {}
+                    // Lost here or by the writer: drop both halves so the
+                    // device node is released before it is opened again
+                    DeviceState::Lost { .. } => port = None,
+                    DeviceState::Closed => break,
+                }
+                let Some(serial) = port.as_mut() else {
+                    writer.lock().unwrap().take();
+                    port = Self::reopen(&settings, &state, &writer, &logger);
+                    deframer = kiss::Deframer::new(max_frame_len);
+                    // A TNC that lost power has also lost its KISS parameters
+                    if port.is_some() {
+                        Self::send_params(&state, &queue, &routes, &device, |_| true, 6, &logger);
+                    }
+                    continue;
+                };
+                let result = serial.read(&mut buffer);
+                if let Err(ref e) = result {
+                    if is_fatal(e) {
+                        state.lock().unwrap().lose(e);
+                    }
+                }
+                match result {
+                    Ok(n) if n > 0 => {
+                        let data = &buffer[..n];
+
+                        if raw_copy {
+                            // Raw copy mode: no framing, every route gets the chunk
+                            let mut routes = routes.lock().unwrap();
+                            routes.retain(|route| route.sender.send(data.to_vec()).is_ok());
+                            continue;
+                        }
+
+                        for item in deframer.push(data) {
+                            match item {
+                                kiss::Deframed::Frame(frame) => {
+                                    if !Self::poll_response(&bus, &frame) {
+                                        Self::dispatch(&routes, frame, &device, &logger);
                                     }
-                                    in_frame = !in_frame;
-                                } else if in_frame {
-                                    frame_buffer.push(byte);
+                                }
+                                kiss::Deframed::Oversize { command, len } => {
+                                    Self::record_error(&routes, command.map(kiss::port),
+                                        FrameError::Oversize, &format!(
+                                            "Oversize KISS frame dropped on {} ({} bytes, max {})",
+                                            device, len, max_frame_len), &logger);
+                                }
+                                kiss::Deframed::Resync(skipped) => {
+                                    Self::record_error(&routes, None, FrameError::Resync,
+                                        &format!("Resynchronised KISS framing on {}, skipped {} bytes",
+                                            device, skipped), &logger);
                                 }
                             }
                         }
-                        Ok(_) => {}
-                        Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
-                        Err(e) => {
-                            logger.log(&format!("[{}] Serial read error: {}", config.id, e), 3);
-                        }
                     }
+                    // Timeouts and empty reads; a fatal error has marked the
+                    // device lost and is logged when reopening starts
+                    Ok(_) | Err(_) => thread::sleep(Duration::from_millis(10)),
                 }
-                thread::sleep(Duration::from_millis(10));
+                Self::send_params(&state, &queue, &routes, &device, |route| route.params.as_ref()
+                    .is_some_and(|params| params.interval.is_some_and(|interval| params.sent.elapsed() >= interval)),
+                    8, &logger);
             }
-        });
-        
-        // XKISS polling thread (if enabled)
-        if self.config.xkiss_mode && self.config.xkiss_polling {
-            let xkiss_buffer = self.xkiss_rx_buffer.clone();
-            let tcp_clients = self.tcp_clients.clone();
-            let config = self.config.clone();
-            let logger = self.logger.clone();
-            let poll_interval = Duration::from_millis(self.config.xkiss_poll_timer_ms);
-            
-            thread::spawn(move || {
-                loop {
-                    thread::sleep(poll_interval);
-                    
-                    let packets = {
-                        let mut buf = xkiss_buffer.lock().unwrap();
-                        buf.poll_flush()
-                    };
-                    
-                    if !packets.is_empty() {
-                        if config.dump_frames {
-                            logger.log(&format!("[{}] Polling flush: {} packets", 
-                                config.id, packets.len()), 7);
-                        }
-                        
-                        for packet in packets {
-                            Self::send_to_all_tcp_clients(&packet, &tcp_clients, &config, &logger);
-                        }
-                    }
+        })
+    }
+
+    // Reopen a lost device with the settings it was opened with and run its
+    // init script, retrying with backoff until it is back or the demux has
+    // been closed. Installs the writer's half of the new port and returns the
+    // reader's, or None once closed.
+    fn reopen(settings: &CrossConnect, state: &Mutex<DeviceState>, writer: &WriterHalf, logger: &Logger)
+        -> Option<Box<dyn SerialPort>> {
+        let device = &settings.serial_port;
+        let since = match *state.lock().unwrap() {
+            DeviceState::Lost { since, ref error } => {
+                logger.log(&format!("[{}] Serial device lost: {} - reopening", device, error), 3);
+                since
+            }
+            _ => return None,
+        };
+
+        let mut reopen_delay = Duration::from_secs(1);
+        let max_reopen_delay = Duration::from_secs(60);
+        loop {
+            // Sleep in steps so close() is noticed promptly
+            let wake = Instant::now() + reopen_delay;
+            while Instant::now() < wake {
+                if *state.lock().unwrap() == DeviceState::Closed {
+                    return None;
                 }
+                thread::sleep(Duration::from_millis(100));
+            }
+
+            let opened = open_serial_port(settings).map_err(|e| e.to_string()).and_then(|(mut serial, path)| {
+                match settings.init_script {
+                    Some(ref init_script) => script::run("init_script", init_script, device, serial.as_mut(), logger)
+                        .map_err(|e| format!("init_script: {}", e)),
+                    None => Ok(()),
+                }?;
+                let half = serial.try_clone().map_err(|e| e.to_string())?;
+                Ok((serial, half, path))
             });
-        }
-        
-        Ok(())
-    }
-    
-    // Helper function to send data to all connected TCP clients
-    fn send_to_all_tcp_clients(
-        data: &[u8],
-        tcp_clients: &Arc<Mutex<Vec<Option<TcpClientInfo>>>>,
-        config: &CrossConnect,
-        logger: &Arc<Logger>
-    ) {
-        let clients = tcp_clients.lock().unwrap();
-        for (i, client) in clients.iter().enumerate() {
-            if let Some(info) = client {
-                if let Ok(mut stream) = info.stream.try_clone() {
-                    if let Err(e) = stream.write_all(data) {
-                        logger.log(&format!("[{}] Client {} write error: {}", 
-                            config.id, i, e), 4);
+            match opened {
+                Ok((serial, half, path)) => {
+                    let mut writer = writer.lock().unwrap();
+                    let mut state = state.lock().unwrap();
+                    if *state == DeviceState::Closed {
+                        return None;
                     }
+                    *writer = Some(half);
+                    *state = DeviceState::Open;
+                    let via = if &path != device { format!(" as {}", path) } else { String::new() };
+                    logger.log(&format!("[{}] Serial device reopened{} after {}s",
+                        device, via, since.elapsed().as_secs()), 5);
+                    return Some(serial);
+                }
+                Err(e) => {
+                    logger.log(&format!("[{}] Reopen failed: {} - retrying in {:?}",
+                        device, e, reopen_delay), 6);
+                    reopen_delay = (reopen_delay * 2).min(max_reopen_delay);
                 }
             }
         }
     }
-    
-    // Helper function for KISSCOPY: broadcast to other clients
-    fn broadcast_to_other_clients(
-        source_index: usize,
-        data: &[u8],
-        tcp_clients: &Arc<Mutex<Vec<Option<TcpClientInfo>>>>,
-        config: &CrossConnect,
-        logger: &Arc<Logger>
-    ) {
-        let clients = tcp_clients.lock().unwrap();
-        for (i, client) in clients.iter().enumerate() {
-            if i == source_index {
-                continue;  // Skip source client
+
+    // Charge a framing error to the cross-connect owning the port, or to the
+    // primary port when the port is unknown or unclaimed
+    fn record_error(routes: &Mutex<Vec<DemuxRoute>>, port: Option<u8>, error: FrameError,
+                    detail: &str, logger: &Logger) {
+        let routes = routes.lock().unwrap();
+        let route = port.and_then(|port| routes.iter().find(|route| route.kiss_port == port))
+            .or_else(|| routes.iter().find(|route| route.is_primary));
+        match route {
+            Some(route) => route.stats.record(error, &route.id, detail, logger),
+            None => logger.log(detail, 4),
+        }
+    }
+
+    // Hand a frame to every route whose port matches, else to the primary port
+    fn dispatch(routes: &Mutex<Vec<DemuxRoute>>, frame: Vec<u8>, device: &str, logger: &Logger) {
+        // Frames whose command byte cannot be decoded go to the primary port,
+        // where the bridge rejects them
+        let port = kiss::command_byte(&frame).map(kiss::port);
+        let mut routes = routes.lock().unwrap();
+        let matched = port.is_some_and(|port| routes.iter().any(|route| route.kiss_port == port));
+
+        let mut delivered = false;
+        routes.retain(|route| {
+            let wanted = if matched { Some(route.kiss_port) == port } else { route.is_primary };
+            if !wanted {
+                return true;
             }
-            
-            if let Some(info) = client {
-                if let Ok(mut stream) = info.stream.try_clone() {
-                    if let Err(e) = stream.write_all(data) {
-                        logger.log(&format!("[{}] KISSCOPY to client {} error: {}", 
-                            config.id, i, e), 4);
-                    }
-                }
+            delivered = true;
+            if route.sender.send(frame.clone()).is_ok() {
+                true
+            } else {
+                logger.log(&format!("[{}] Route for {} closed", device, route.id), 6);
+                false
             }
+        });
+
+        if !delivered {
+            logger.log(&format!("[{}] No cross-connect for KISS port {:?}, frame dropped",
+                device, port), 7);
         }
     }
-    
-    // Helper function to remap KISS channel from app (channel 0) to configured channel
-    fn remap_kiss_channel_in(data: &[u8], target_channel: u8) -> Vec<u8> {
-        if data.len() < 2 || data[0] != KISS_FEND {
-            return data.to_vec();
+}
+
+// One cross-connect: its route on the serial device and the far endpoints
+// it links that route to, joined by a shared processing pipeline (see
+// transport.rs and pipeline.rs), plus its AGW listener
+struct CrossConnectBridge {
+    config: CrossConnect,
+    peer: Option<CrossConnect>,                             // Far device of a serial_to_serial link
+    pipeline: Arc<pipeline::Pipeline>,
+    max_clients: usize,                                     // From global config
+    agw_clients: Arc<Mutex<Vec<Option<AgwClientInfo>>>>,  // AGW clients
+    agw_enabled: bool,                                      // AGW enabled for this bridge
+    logger: Arc<Logger>,
+    running: Arc<AtomicBool>,                               // Cleared by stop()
+    listeners: Mutex<Vec<thread::JoinHandle<()>>>,          // AGW accept loops, joined by stop()
+}
+
+// How often a listener checks whether its bridge has been stopped
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+impl CrossConnectBridge {
+    // `endpoints` starts with this cross-connect's route on its serial device,
+    // followed by the endpoints linked to it. Framing errors on the serial
+    // routes are counted in `stats`.
+    fn new(config: CrossConnect, peer: Option<CrossConnect>, endpoints: Vec<pipeline::Endpoint>,
+           stats: Arc<FrameStats>, max_clients: usize, logger: Arc<Logger>,
+           pcap_writer: Option<Arc<PcapWriter>>) -> Self {
+        let agw_clients = Arc::new(Mutex::new((0..max_clients).map(|_| None).collect()));
+        let pipeline = Arc::new(pipeline::Pipeline::new(config.clone(), endpoints, stats,
+            agw_clients.clone(), logger.clone(), pcap_writer));
+        let agw_enabled = config.agw_enable;
+
+        CrossConnectBridge {
+            config,
+            peer,
+            pipeline,
+            max_clients,
+            agw_clients,
+            agw_enabled,
+            logger,
+            running: Arc::new(AtomicBool::new(true)),
+            listeners: Mutex::new(Vec::new()),
         }
-        
-        let mut result = data.to_vec();
-        let kiss_byte = data[1];
-        let cmd = kiss_byte & 0x0F;
-        result[1] = (target_channel << 4) | cmd;
-        result
     }
 
+    // Start the far endpoints, then the serial route feeding them, then the
+    // AGW listener if `agw` gives its address. A cross-connect with nothing
+    // to feed leaves its KISS port to the primary port of the device.
+    fn start(&self, agw: Option<(String, u16)>) -> Result<(), Box<dyn std::error::Error>> {
+        let endpoints = self.pipeline.endpoints();
+        for (index, endpoint) in endpoints.iter().enumerate().skip(1) {
+            endpoint.transport.start(self.pipeline.sink(index))?;
+        }
+        if endpoints.len() > 1 || agw.is_some() {
+            self.pipeline.device().start(self.pipeline.sink(0))?;
+        }
+        if let Some((agw_address, agw_port)) = agw {
+            self.start_agw_listener(agw_address, agw_port)?;
+        }
+        Ok(())
+    }
+
+    // Stop the bridge: close its endpoints, AGW listener and clients and detach
+    // it from the serial demultiplexer(s). Other cross-connects on the same
+    // device keep running.
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        for listener in self.listeners.lock().unwrap().drain(..) {
+            let _ = listener.join();
+        }
+        for info in self.agw_clients.lock().unwrap().iter().flatten() {
+            let _ = info.stream.shutdown(Shutdown::Both);
+        }
+        for endpoint in self.pipeline.endpoints() {
+            endpoint.transport.stop();
+        }
+        self.logger.log(&format!("[{}] Stopped", self.config.id), 6);
+    }
 
     fn start_agw_listener(&self, agw_address: String, agw_port: u16) 
         -> Result<(), Box<dyn std::error::Error>> {
@@ -1378,20 +1578,24 @@ impl CrossConnectBridge {
         
         let bind_address = format!("{}:{}", agw_address, agw_port);
         let listener = TcpListener::bind(&bind_address)?;
+        listener.set_nonblocking(true)?;
         self.logger.log(&format!("[{}] AGW listener on {} (max {} clients)", 
             self.config.id, bind_address, self.max_clients), 5);
         
         let agw_clients = self.agw_clients.clone();
         let max_clients = self.max_clients;
-        let serial = self.serial_port.clone();
+        let device = self.pipeline.device().clone();
+        let filter = self.pipeline.filter().clone();
         let config = self.config.clone();
         let logger = self.logger.clone();
+        let running = self.running.clone();
         
         // Accept loop thread
-        thread::spawn(move || {
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(client_stream) => {
+        let handle = thread::spawn(move || {
+            while running.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((client_stream, _)) => {
+                        let _ = client_stream.set_nonblocking(false);
                         let peer_addr = client_stream.peer_addr().ok();
                         let mut clients = agw_clients.lock().unwrap();
                         
@@ -1415,7 +1619,8 @@ impl CrossConnectBridge {
                             
                             // Spawn read thread for this AGW client
                             let agw_clients_clone = agw_clients.clone();
-                            let serial_clone = serial.clone();
+                            let device_clone = device.clone();
+                            let filter_clone = filter.clone();
                             let config_clone = config.clone();
                             let logger_clone = logger.clone();
                             
@@ -1521,15 +1726,26 @@ impl CrossConnectBridge {
                                                                     config_clone.id, index, data_len), 7);
                                                             }
                                                             
+                                                            // AGW transmits data frames only, which the
+                                                            // KISS command filter may still drop (receive-only clients)
+                                                            let client = peer_addr.map(firewall::Client::Address);
+                                                            if filter_clone.verdict(client, config_clone.wire_port() << 4) == firewall::Action::Drop {
+                                                                logger_clone.log(&format!(
+                                                                    "[{}] AGW client {} ({}): data dropped by KISS command filter",
+                                                                    config_clone.id, index,
+                                                                    client.map_or("unknown address".to_string(), |client| client.to_string())), 5);
+                                                                offset = frame_end;
+                                                                continue;
+                                                            }
+                                                            
                                                             // Convert AGW to KISS and send to serial
                                                             let kiss_frame = agw_to_kiss(&header, frame_data, &config_clone);
                                                             
-                                                            if let Ok(mut port) = serial_clone.lock() {
-                                                                if let Err(e) = port.write_all(&kiss_frame) {
-                                                                    logger_clone.log(&format!(
-                                                                        "[{}] Serial write error: {}", 
-                                                                        config_clone.id, e), 4);
-                                                                }
+                                                            let unit = transport::Outgoing::frame(&kiss_frame, config_clone.wire_port());
+                                                            if let Err(e) = device_clone.send(transport::Target::All, unit) {
+                                                                logger_clone.log(&format!(
+                                                                    "[{}] Serial write error: {}", 
+                                                                    config_clone.id, e), 4);
                                                             }
                                                         }
                                                         _ => {
@@ -1570,59 +1786,314 @@ impl CrossConnectBridge {
                                 config.id, peer_addr, max_clients), 4);
                         }
                     }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        thread::sleep(ACCEPT_POLL_INTERVAL);
+                    }
                     Err(e) => {
                         logger.log(&format!("[{}] AGW accept error: {}", config.id, e), 3);
                     }
                 }
             }
         });
+        self.listeners.lock().unwrap().push(handle);
         
         Ok(())
     }
-    fn translate_kiss_port_static(data: &[u8], config: &CrossConnect, from_xkiss: bool, to_xkiss: bool) -> Vec<u8> {
-        if data.is_empty() || (!from_xkiss && !to_xkiss) {
-            return data.to_vec();
+}
+
+// Settings for the far device of a serial_to_serial cross-connect: those of
+// the cross-connect that defines that device, or this cross-connect's line
+// settings if no other cross-connect does
+fn peer_config(config: &Config, cc: &CrossConnect) -> Option<CrossConnect> {
+    let peer_device = cc.serial_to_serial.as_ref()?;
+    Some(config.cross_connects.iter()
+        .filter(|other| &other.serial_port == peer_device)
+        .max_by_key(|other| other.is_primary_port)
+        .cloned()
+        .unwrap_or_else(|| CrossConnect {
+            id: format!("{}.peer", cc.id),
+            serial_port: peer_device.clone(),
+            init_script: None,
+            exit_script: None,
+            txdelay: None,
+            persist: None,
+            slottime: None,
+            txtail: None,
+            fullduplex: None,
+            xkiss_mode: false,
+            xkiss_port: None,
+            serial_to_serial: None,
+            pty_link: None,
+            phil_flag: false,
+            is_primary_port: true,
+            ..cc.clone()
+        }))
+}
+
+// Settings each serial device is opened with: those of its primary
+// cross-connect, or the peer settings for a device that is only the far end
+// of a serial_to_serial cross-connect
+fn device_settings(config: &Config) -> HashMap<String, CrossConnect> {
+    let mut devices: HashMap<String, CrossConnect> = config.cross_connects.iter()
+        .filter(|cc| cc.is_primary_port)
+        .map(|cc| (cc.serial_port.clone(), cc.clone()))
+        .collect();
+    for cc in &config.cross_connects {
+        if let Some(peer) = peer_config(config, cc) {
+            devices.entry(peer.serial_port.clone()).or_insert(peer);
         }
+    }
+    devices
+}
 
-        let mut result = Vec::with_capacity(data.len());
-        
-        if from_xkiss && !to_xkiss {
-            if let Some(_xkiss_port) = config.xkiss_port {
-                for &byte in data {
-                    if byte == KISS_FEND {
-                        result.push(byte);
-                    } else if result.len() == 1 {
-                        let cmd_type = byte & 0x0F;
-                        let new_byte = (config.kiss_port << 4) | cmd_type;
-                        result.push(new_byte);
-                    } else {
-                        result.push(byte);
+// The running cross-connect bridges and the serial devices they share.
+//
+// On SIGHUP reload() compares the new configuration with what is running.
+// Bridges whose settings changed are restarted, removed ones are stopped and
+// new ones started; unchanged bridges and their clients stay up. A serial
+// device is only reopened when its line settings change.
+struct BridgeSet {
+    config: Config,
+    bridges: Vec<CrossConnectBridge>,
+    devices: HashMap<String, (CrossConnect, Arc<SerialDemux>)>,  // Settings each demux was opened with
+    logger: Arc<Logger>,
+    pcap_writer: Option<Arc<PcapWriter>>,
+}
+
+impl BridgeSet {
+    fn start(config: Config, logger: Arc<Logger>) -> Result<Self, Box<dyn std::error::Error>> {
+        let pcap_writer = if let Some(ref pcap_path) = config.pcap_file {
+            Some(Arc::new(PcapWriter::new(pcap_path)?))
+        } else {
+            None
+        };
+        if config.agw_server_enable {
+            logger.log(&format!("Starting AGW server on {}:{} (max {} clients)", 
+                config.agw_server_address, config.agw_server_port, config.agw_max_clients), 5);
+        }
+
+        let settings = device_settings(&config);
+        let cross_connects = config.cross_connects.clone();
+        let mut set = BridgeSet {
+            config,
+            bridges: Vec::new(),
+            devices: HashMap::new(),
+            logger,
+            pcap_writer,
+        };
+        for cc in &cross_connects {
+            let bridge = set.launch(cc, &settings)?;
+            set.bridges.push(bridge);
+        }
+        Ok(set)
+    }
+
+    // Demultiplexer for a serial device, opening it with `settings` if needed
+    fn open_device(&mut self, settings: &CrossConnect) -> Result<Arc<SerialDemux>, Box<dyn std::error::Error>> {
+        if let Some((_, demux)) = self.devices.get(&settings.serial_port) {
+            return Ok(demux.clone());
+        }
+        self.logger.log(&format!("[{}] Opening {} @ {} baud",
+            settings.id, settings.serial_port, settings.baud_rate), 6);
+        let (mut port, path) = open_serial_port(settings)
+            .map_err(|e| format!("{}: {}", settings.serial_port, e))?;
+        if path != settings.serial_port {
+            self.logger.log(&format!("[{}] {} is {}", settings.id, settings.serial_port, path), 6);
+        }
+        if let Some(ref init_script) = settings.init_script {
+            script::run("init_script", init_script, &settings.serial_port, port.as_mut(), &self.logger)
+                .map_err(|e| format!("{}: init_script: {}", settings.serial_port, e))?;
+        }
+        let demux = SerialDemux::new(settings, port, self.logger.clone())
+            .map_err(|e| format!("{}: {}", settings.serial_port, e))?;
+        self.devices.insert(settings.serial_port.clone(), (settings.clone(), demux.clone()));
+        Ok(demux)
+    }
+
+    // Create and start the bridge for one cross-connect of the current configuration
+    fn launch(&mut self, cc: &CrossConnect, settings: &HashMap<String, CrossConnect>)
+        -> Result<CrossConnectBridge, Box<dyn std::error::Error>> {
+        if cc.is_primary_port {
+            self.logger.log(&format!("[{}] Creating primary port (KISS port {})", cc.id, cc.kiss_port), 6);
+        } else {
+            self.logger.log(&format!("[{}] Creating secondary port (KISS port {}) sharing {}", 
+                cc.id, cc.kiss_port, cc.serial_port), 6);
+        }
+
+        let stats = Arc::new(FrameStats::default());
+        let demux = self.open_device(&settings[&cc.serial_port])?;
+        let route = transport::SerialRoute::new(cc, cc, demux, stats.clone(), self.logger.clone());
+        let mut endpoints = vec![pipeline::Endpoint {
+            transport: Arc::new(route),
+            config: cc.clone(),
+            role: pipeline::Role::Tnc,
+        }];
+        let host = |transport: Arc<dyn transport::Transport>| pipeline::Endpoint {
+            transport,
+            config: cc.clone(),
+            role: pipeline::Role::Host,
+        };
+
+        let peer = peer_config(&self.config, cc);
+        if let Some(ref peer) = peer {
+            let peer_demux = self.open_device(&settings[&peer.serial_port])?;
+            self.logger.log(&format!("[{}] Serial-to-serial: {} ({}) <-> {} ({})",
+                cc.id,
+                cc.serial_port, if cc.xkiss_mode { "XKISS" } else { "KISS" },
+                peer.serial_port, if peer.xkiss_mode { "XKISS" } else { "KISS" }), 5);
+            let route = transport::SerialRoute::new(cc, peer, peer_demux, stats.clone(), self.logger.clone());
+            endpoints.push(pipeline::Endpoint {
+                transport: Arc::new(route),
+                config: peer.clone(),
+                role: pipeline::Role::Tnc,
+            });
+        } else {
+            match cc.tcp_mode {
+                TcpMode::Server => {
+                    endpoints.push(host(Arc::new(transport::TcpServer::new(cc,
+                        self.config.max_tcp_clients, self.logger.clone()))));
+                }
+                TcpMode::Client => {
+                    if cc.tcp_to_tcp_dangerous {
+                        self.logger.log(&format!("[{}] WARNING: TCP-to-TCP mode enabled - use with caution!", 
+                            cc.id), 4);
                     }
+                    endpoints.push(host(Arc::new(transport::TcpClient::new(cc, self.logger.clone())?)));
+                }
+                TcpMode::None if cc.pty_link.is_some() => {}
+                TcpMode::None => {
+                    self.logger.log(&format!("[{}] No TCP mode configured, serial only", 
+                        cc.id), 6);
                 }
-            } else {
-                result = data.to_vec();
             }
-        } else if !from_xkiss && to_xkiss {
-            if let Some(xkiss_port) = config.xkiss_port {
-                for &byte in data {
-                    if byte == KISS_FEND {
-                        result.push(byte);
-                    } else if result.len() == 1 {
-                        let cmd_type = byte & 0x0F;
-                        let new_byte = (xkiss_port << 4) | cmd_type;
-                        result.push(new_byte);
-                    } else {
-                        result.push(byte);
-                    }
+            if let Some(ref link) = cc.pty_link {
+                endpoints.push(host(transport::pty(cc, link, self.logger.clone())?));
+            }
+        }
+
+        let bridge = CrossConnectBridge::new(cc.clone(), peer, endpoints, stats,
+            self.config.max_tcp_clients, self.logger.clone(), self.pcap_writer.clone());
+        let agw = (self.config.agw_server_enable && bridge.agw_enabled)
+            .then(|| (self.config.agw_server_address.clone(), self.config.agw_server_port));
+        let agw_enabled = agw.is_some();
+        if let Err(e) = bridge.start(agw) {
+            bridge.stop();
+            return Err(e);
+        }
+        if agw_enabled {
+            self.logger.log(&format!("[{}] AGW enabled on port {}", 
+                cc.id, cc.agw_port), 6);
+        }
+        Ok(bridge)
+    }
+
+    // Orderly shutdown (SIGINT, SIGTERM): stop every bridge, then close the
+    // serial devices so their exit scripts run
+    fn shutdown(&mut self) {
+        for bridge in self.bridges.drain(..) {
+            bridge.stop();
+        }
+        for (_, (_, demux)) in self.devices.drain() {
+            demux.close();
+        }
+    }
+
+    // Log the state of every serial device (SIGUSR1)
+    fn log_status(&self) {
+        let mut devices: Vec<_> = self.devices.iter().collect();
+        devices.sort_by_key(|(device, _)| device.as_str());
+        for (device, (_, demux)) in devices {
+            let state = match demux.state() {
+                DeviceState::Open => "open".to_string(),
+                DeviceState::Lost { since, error } => format!("lost {}s ago ({}), reopening",
+                    since.elapsed().as_secs(), error),
+                DeviceState::Closed => "closed".to_string(),
+            };
+            let users: Vec<&str> = self.bridges.iter()
+                .filter(|bridge| &bridge.config.serial_port == device
+                    || bridge.peer.as_ref().is_some_and(|peer| &peer.serial_port == device))
+                .map(|bridge| bridge.config.id.as_str())
+                .collect();
+            self.logger.log(&format!("[{}] Serial device {}, used by {}", device, state,
+                if users.is_empty() { "no cross-connect".to_string() } else { users.join(", ") }), 5);
+        }
+    }
+
+    // Switch to a reloaded configuration, restarting only what changed
+    fn reload(&mut self, config: Config) {
+        let logger = self.logger.clone();
+        if let Err(e) = logger.reconfigure(config.logfile.as_deref(), config.log_level,
+                                           config.log_to_console && !config.log_to_file_only) {
+            logger.log(&format!("Cannot open log file {}: {} - keeping the current one",
+                config.logfile.as_deref().unwrap_or_default(), e), 3);
+        }
+        if config.pidfile != self.config.pidfile {
+            logger.log("pidfile change takes effect when rax25kb is restarted", 4);
+        }
+
+        // Every bridge holds the PCAP writer and its client limit, so a change
+        // to either restarts them all
+        let mut restart_all = config.max_tcp_clients != self.config.max_tcp_clients;
+        if config.pcap_file != self.config.pcap_file {
+            restart_all = true;
+            self.pcap_writer = config.pcap_file.as_ref().and_then(|path| match PcapWriter::new(path) {
+                Ok(writer) => Some(Arc::new(writer)),
+                Err(e) => {
+                    logger.log(&format!("Cannot create PCAP file {}: {}", path, e), 3);
+                    None
                 }
-            } else {
-                result = data.to_vec();
+            });
+        }
+        let agw_changed = config.agw_server_enable != self.config.agw_server_enable
+            || config.agw_server_address != self.config.agw_server_address
+            || config.agw_server_port != self.config.agw_server_port;
+
+        // Devices whose line settings changed, or that nothing uses any more
+        let settings = device_settings(&config);
+        let closing: Vec<String> = self.devices.iter()
+            .filter(|(device, (opened, _))| !settings.get(*device).is_some_and(|new| new.same_line(opened)))
+            .map(|(device, _)| device.clone())
+            .collect();
+
+        let (kept, stopped): (Vec<_>, Vec<_>) = std::mem::take(&mut self.bridges).into_iter()
+            .partition(|bridge| {
+                if restart_all || (agw_changed && bridge.agw_enabled) {
+                    return false;
+                }
+                let peer_unchanged = match (&bridge.peer, peer_config(&config, &bridge.config)) {
+                    (None, None) => true,
+                    (Some(peer), Some(new)) => *peer == new && !closing.contains(&new.serial_port),
+                    _ => false,
+                };
+                config.cross_connects.contains(&bridge.config)
+                    && !closing.contains(&bridge.config.serial_port)
+                    && peer_unchanged
+            });
+
+        for bridge in &stopped {
+            bridge.stop();
+        }
+        for device in &closing {
+            if let Some((_, demux)) = self.devices.remove(device) {
+                demux.close();
             }
-        } else {
-            result = data.to_vec();
         }
-        
-        result
+
+        self.bridges = kept;
+        self.config = config;
+        let unchanged = self.bridges.len();
+        for cc in self.config.cross_connects.clone() {
+            if self.bridges.iter().any(|bridge| bridge.config.slot == cc.slot) {
+                continue;
+            }
+            match self.launch(&cc, &settings) {
+                Ok(bridge) => self.bridges.push(bridge),
+                Err(e) => logger.log(&format!("[{}] Not started: {}", cc.id, e), 3),
+            }
+        }
+
+        logger.log(&format!("Configuration reloaded: {} cross-connect(s) unchanged, {} stopped, {} started",
+            unchanged, stopped.len(), self.bridges.len() - unchanged), 5);
     }
 }
 
@@ -1630,12 +2101,12 @@ fn process_frame_with_phil_flag(frame: &[u8]) -> Vec<u8> {
     if frame.len() < 2 { return frame.to_vec(); }
     let mut output = Vec::with_capacity(frame.len() * 2);
     output.push(frame[0]);
-    for i in 1..frame.len()-1 {
-        if frame[i] == KISS_FEND {
-            output.push(KISS_FESC);
-            output.push(KISS_TFEND);
+    for &byte in &frame[1..frame.len()-1] {
+        if byte == kiss::FEND {
+            output.push(kiss::FESC);
+            output.push(kiss::TFEND);
         } else {
-            output.push(frame[i]);
+            output.push(byte);
         }
     }
     if frame.len() > 1 { output.push(frame[frame.len()-1]); }
@@ -1646,7 +2117,7 @@ fn process_phil_flag_tcp_to_serial(data: &[u8]) -> Vec<u8> {
     let mut output = Vec::with_capacity(data.len() * 2);
     for &byte in data {
         if byte == 0x43 || byte == 0x63 {
-            output.push(KISS_FESC);
+            output.push(kiss::FESC);
             output.push(byte);
         } else {
             output.push(byte);
@@ -1655,12 +2126,12 @@ fn process_phil_flag_tcp_to_serial(data: &[u8]) -> Vec<u8> {
     output
 }
 
-#[allow(dead_code)] // Used when xkiss_checksum is enabled
+// XKISS (BPQ) checksum: XOR of every unescaped byte of the frame, command byte included
 fn calculate_xkiss_checksum(data: &[u8]) -> u8 {
-    data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b))
+    data.iter().fold(0u8, |acc, &b| acc ^ b)
 }
 
-#[allow(dead_code)] // Used when xkiss_checksum is enabled
+// Check the trailing checksum byte of a decoded XKISS frame
 fn verify_xkiss_checksum(frame: &[u8]) -> bool {
     if frame.len() < 2 {
         return false;
@@ -1670,11 +2141,32 @@ fn verify_xkiss_checksum(frame: &[u8]) -> bool {
     calculate_xkiss_checksum(data) == checksum
 }
 
-fn is_kiss_packet(data: &[u8]) -> bool {
-    if data.len() < 2 {
-        return false;
+// Encode a decoded frame for a TCP client, with the XKISS checksum when emulating a checksummed TNC
+fn host_frame(frame: &[u8], config: &CrossConnect) -> Vec<u8> {
+    if config.xkiss_emulation && config.xkiss_checksum {
+        let mut checked = frame.to_vec();
+        checked.push(calculate_xkiss_checksum(frame));
+        kiss::encode(&checked)
+    } else {
+        kiss::encode(frame)
+    }
+}
+
+// Encode a decoded frame for a serial TNC: XKISS checksum (before escaping,
+// so a checksum of FEND/FESC is escaped too), KISS framing, then PhilFlag
+fn encode_for_serial(frame: &[u8], config: &CrossConnect) -> Vec<u8> {
+    let encoded = if config.xkiss_mode && config.xkiss_checksum {
+        let mut checked = frame.to_vec();
+        checked.push(calculate_xkiss_checksum(frame));
+        kiss::encode(&checked)
+    } else {
+        kiss::encode(frame)
+    };
+    if config.phil_flag {
+        process_phil_flag_tcp_to_serial(&encoded)
+    } else {
+        encoded
     }
-    data[0] == KISS_FEND && data[data.len()-1] == KISS_FEND
 }
 
 #[allow(dead_code)] // Used when reframe_large_packets is enabled
@@ -1734,10 +2226,10 @@ fn reframe_large_packet(packet: &[u8], phil_flag: bool, max_size: usize) -> Vec<
     let mut fragments = Vec::new();
     
     for chunk in info.chunks(conservative_chunk_size) {
-        let mut frag = vec![KISS_FEND, kiss_cmd];
+        let mut frag = vec![kiss::FEND, kiss_cmd];
         frag.extend_from_slice(header);
         frag.extend_from_slice(chunk);
-        frag.push(KISS_FEND);
+        frag.push(kiss::FEND);
         fragments.push(frag);
     }
     
@@ -1775,8 +2267,8 @@ fn extract_callsign(data: &[u8]) -> String {
     let mut call = String::new();
     
     // Extract callsign (first 6 bytes, shifted right by 1)
-    for i in 0..6 {
-        let c = (data[i] >> 1) & 0x7F;
+    for &byte in &data[..6] {
+        let c = (byte >> 1) & 0x7F;
         if c != 0x20 {  // Skip spaces
             call.push(c as char);
         }
@@ -1818,21 +2310,13 @@ fn build_agw_frame(kind: u8, port: u32, from: &str, to: &str, pid: u8, data: &[u
 
 // Convert KISS frame to AGW frame
 fn kiss_to_agw(kiss_frame: &[u8], agw_port: u8, monitor_mode: bool) -> Vec<u8> {
-    // Extract AX.25 data from KISS frame
-    // KISS format: [FEND][CMD][AX.25 DATA][FEND]
-    if kiss_frame.len() < 4 {
-        return Vec::new();
-    }
-    
-    // Skip FEND and CMD byte, take until final FEND
-    let ax25_start = 2;
-    let ax25_end = kiss_frame.len() - 1;
-    
-    if ax25_end <= ax25_start {
+    // Extract AX.25 data from a decoded KISS frame
+    // Decoded format: [CMD][AX.25 DATA]; only data frames carry AX.25
+    if kiss_frame.len() < 2 || kiss::command(kiss_frame[0]) != 0 {
         return Vec::new();
     }
     
-    let ax25_data = &kiss_frame[ax25_start..ax25_end];
+    let ax25_data = &kiss_frame[1..];
     
     // Extract addresses
     let (from_call, to_call) = extract_ax25_addresses(ax25_data);
@@ -1849,20 +2333,13 @@ fn agw_to_kiss(_agw_header: &AgwHeader, agw_data: &[u8], config: &CrossConnect)
     // Extract AX.25 data from AGW frame
     let ax25_data = agw_data;
     
-    // Apply PhilFlag escaping if enabled (same as KISS TCP clients)
-    let processed_data = if config.phil_flag {
-        process_phil_flag_tcp_to_serial(ax25_data)
-    } else {
-        ax25_data.to_vec()
-    };
-    
-    // Build KISS frame
-    let mut kiss_frame = vec![KISS_FEND];
-    kiss_frame.push((config.kiss_port << 4) | 0x00);  // Data frame command
-    kiss_frame.extend_from_slice(&processed_data);
-    kiss_frame.push(KISS_FEND);
+    // Build KISS data frame (command 0) on this cross-connect's wire port
+    let mut frame = Vec::with_capacity(ax25_data.len() + 1);
+    frame.push(config.wire_port() << 4);
+    frame.extend_from_slice(ax25_data);
     
-    kiss_frame
+    // XKISS checksum and PhilFlag escaping if enabled (same as KISS TCP clients)
+    encode_for_serial(&frame, config)
 }
 
 // Send AGW frame to client
@@ -1872,7 +2349,7 @@ fn send_agw_frame(stream: &mut TcpStream, frame: &[u8]) -> std::io::Result<()> {
 
 // Handle AGW Port Information Request ('G')
 fn handle_agw_port_info(stream: &mut TcpStream, header: &AgwHeader) -> std::io::Result<()> {
-    let version_info = format!("rax25kb v1.7.3 AGW");
+    let version_info = "rax25kb v1.7.3 AGW";
     let response = build_agw_frame(b'G', header.port, "", "", 0, version_info.as_bytes());
     send_agw_frame(stream, &response)
 }
@@ -2005,6 +2482,14 @@ fn show_help(program_name: &str) {
     println!("  -P, --pidfile <file>  PID file path");
     println!("  --pcap <file>         Write AX.25 frames to PCAP file");
     println!("  -c <file>             Config file (default: rax25kb.cfg)");
+    println!("  --check-config        Validate the config file and exit (non-zero on errors)");
+    println!("  --convert-config <file>  Print a key=value config file in TOML format");
+    println!("  --migrate-config <file>  Print a legacy single-bridge config file in the");
+    println!("                        cross-connect format, warning about ignored keys");
+    println!("  --print-config        Print the effective configuration (includes, templates");
+    println!("                        and defaults applied) and exit");
+    println!("  --set <key=value>     Set any config key, e.g. cross_connect0001.baud_rate=19200");
+    println!("  --list-ports          List serial ports with their usb: and by-id: selectors");
     println!("  -q, --quiet           Quiet startup");
     println!("  -h, --help            Show this help\n");
     println!("KISS TNC Defaults: 8N1 (8 data bits, No parity, 1 stop bit), No flow control");
@@ -2016,12 +2501,15 @@ fn show_help(program_name: &str) {
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let running = Arc::new(std::sync::atomic::AtomicBool::new(true));
-    let r = running.clone();
+    // SIGINT (and SIGTERM, below) ask the main loop to shut down; a second
+    // SIGINT exits at once
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let s = shutdown.clone();
     ctrlc::set_handler(move || {
+        if s.swap(true, Ordering::SeqCst) {
+            std::process::exit(1);
+        }
         println!("\nReceived SIGINT, shutting down gracefully...");
-        r.store(false, std::sync::atomic::Ordering::SeqCst);
-        std::process::exit(0);
     })?;
     
     let args: Vec<String> = std::env::args().collect();
@@ -2036,8 +2524,72 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .map(|s| s.as_str())
         .unwrap_or("rax25kb.cfg");
     
-    let mut config = Config::from_file(config_file)?;
-    config.apply_cli_overrides(&args);
+    if let Some(i) = args.iter().position(|arg| arg == "--convert-config") {
+        let path = args.get(i + 1).map(|s| s.as_str())
+            .filter(|path| !path.starts_with('-'))
+            .unwrap_or(config_file);
+        match config::convert_to_toml(path) {
+            Ok(toml) => {
+                print!("{}", toml);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    
+    if args.iter().any(|arg| arg == "--list-ports") {
+        match ports::list() {
+            Ok(list) => {
+                print!("{}", list);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    
+    if let Some(i) = args.iter().position(|arg| arg == "--migrate-config") {
+        let path = args.get(i + 1).map(|s| s.as_str())
+            .filter(|path| !path.starts_with('-'))
+            .unwrap_or(config_file);
+        match config::migrate_legacy(path) {
+            Ok((migrated, warnings)) => {
+                for warning in &warnings {
+                    eprintln!("warning: {}", warning);
+                }
+                print!("{}", migrated);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    
+    let config = match Config::load(config_file, &args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    
+    if args.iter().any(|arg| arg == "--check-config") {
+        println!("{}: OK ({} cross-connect{})", config_file, config.cross_connects.len(),
+            if config.cross_connects.len() == 1 { "" } else { "s" });
+        return Ok(());
+    }
+    
+    if args.iter().any(|arg| arg == "--print-config") {
+        print!("{}", config::print_config(&config));
+        return Ok(());
+    }
     
     if !config.quiet_startup {
         println!("rax25kb - AX.25 KISS Bridge v1.7.3");
@@ -2074,84 +2626,48 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
     
-    let logger = Arc::new(Logger::new(config.logfile.clone(), config.log_level, config.log_to_console)?);
+    let log_to_console = config.log_to_console && !config.log_to_file_only;
+    let logger = Arc::new(Logger::new(config.logfile.clone(), config.log_level, log_to_console)?);
     logger.log("rax25kb v1.7.3 starting", 5);
 
-    let pcap_writer = if let Some(ref pcap_path) = config.pcap_file {
-        Some(Arc::new(PcapWriter::new(pcap_path)?))
-    } else {
-        None
-    };
-
-    let max_tcp_clients = config.max_tcp_clients;
-    let mut bridges: Vec<CrossConnectBridge> = Vec::new();
-    let mut serial_handles: HashMap<String, Arc<Mutex<Box<dyn SerialPort>>>> = HashMap::new();
-    
-    // First pass: Create primary ports (KISS port 0) and collect serial handles
-    for cc in config.cross_connects.iter().filter(|cc| cc.is_primary_port) {
-        logger.log(&format!("[{}] Creating primary port (KISS port {})", cc.id, cc.kiss_port), 6);
-        let bridge = CrossConnectBridge::new(cc.clone(), max_tcp_clients, None, logger.clone(), pcap_writer.clone())?;
-        serial_handles.insert(cc.serial_port.clone(), bridge.serial_port.clone());
-        bridges.push(bridge);
-    }
+    let mut bridges = BridgeSet::start(config, logger.clone())?;
+    logger.log("All cross-connects started", 5);
     
-    // Second pass: Create secondary ports (KISS ports 1-15) with shared handles
-    for cc in config.cross_connects.iter().filter(|cc| !cc.is_primary_port) {
-        logger.log(&format!("[{}] Creating secondary port (KISS port {}) sharing {}", 
-            cc.id, cc.kiss_port, cc.serial_port), 6);
-        let shared = serial_handles.get(&cc.serial_port).cloned();
-        if shared.is_none() {
-            logger.log(&format!("[{}] WARNING: No primary port found for serial {}, creating as primary", 
-                cc.id, cc.serial_port), 4);
-        }
-        let bridge = CrossConnectBridge::new(cc.clone(), max_tcp_clients, shared, logger.clone(), pcap_writer.clone())?;
-        bridges.push(bridge);
-    }
+    // SIGHUP: re-read the configuration (with the same command-line overrides)
+    let reload = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, reload.clone())?;
+    // SIGUSR1: log the state of the serial devices
+    let status = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGUSR1, status.clone())?;
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, shutdown.clone())?;
     
-    for bridge in &bridges {
-        if bridge.config.serial_to_serial.is_none() {
-            match bridge.config.tcp_mode {
-                TcpMode::Server => {
-                    bridge.start_tcp_listener()?;
-                }
-                TcpMode::Client => {
-                    if bridge.config.tcp_to_tcp_dangerous {
-                        logger.log(&format!("[{}] WARNING: TCP-to-TCP mode enabled - use with caution!", 
-                            bridge.config.id), 4);
-                    }
-                    bridge.start_tcp_client()?;
-                }
-                TcpMode::None => {
-                    logger.log(&format!("[{}] No TCP mode configured, serial only", 
-                        bridge.config.id), 6);
-                }
-            }
+    loop {
+        thread::sleep(Duration::from_millis(250));
+        if shutdown.load(Ordering::SeqCst) {
+            logger.log("Shutting down", 5);
+            bridges.shutdown();
+            return Ok(());
         }
-    }
-    
-    // Start AGW server if enabled globally
-    if config.agw_server_enable {
-        logger.log(&format!("Starting AGW server on {}:{} (max {} clients)", 
-            config.agw_server_address, config.agw_server_port, config.agw_max_clients), 5);
-        
-        // Start AGW listener on each bridge with AGW enabled
-        for bridge in &bridges {
-            if bridge.agw_enabled {
-                bridge.start_agw_listener(
-                    config.agw_server_address.clone(),
-                    config.agw_server_port
-                )?;
-                logger.log(&format!("[{}] AGW enabled on port {}", 
-                    bridge.config.id, bridge.config.agw_port), 6);
+        if status.swap(false, Ordering::SeqCst) {
+            bridges.log_status();
+        }
+        if !reload.swap(false, Ordering::SeqCst) {
+            continue;
+        }
+        logger.log(&format!("SIGHUP received, reloading {}", config_file), 5);
+        match Config::load(config_file, &args) {
+            Ok(config) => bridges.reload(config),
+            Err(e) => {
+                for line in e.to_string().lines() {
+                    logger.log(line, 3);
+                }
+                logger.log("Reload failed, keeping the running configuration", 3);
             }
         }
     }
-    
-    logger.log("All cross-connects started", 5);
-    
-    loop {
-        thread::sleep(Duration::from_secs(1));
-    }
 }
 
 fn parse_kiss_frame(frame: &[u8], logger: &Logger, bridge_id: &str) {
@@ -2170,6 +2686,8 @@ fn parse_kiss_frame(frame: &[u8], logger: &Logger, bridge_id: &str) {
         4 => "TX Tail",
         5 => "Full Duplex",
         6 => "Set Hardware",
+        12 => "ACKMODE",
+        14 => "Poll",
         15 => "Return",
         _ => "Unknown",
     };
@@ -2187,3 +2705,178 @@ fn parse_kiss_frame(frame: &[u8], logger: &Logger, bridge_id: &str) {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    // A directory of its own in the temporary directory for one test,
+    // removed with everything in it when dropped
+    pub(crate) struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("rax25kb-{}-{}", std::process::id(), name));
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+            &self.0
+        }
+
+        // Write `contents` to `name` in the directory; returns the file's path
+        pub(crate) fn file(&self, name: &str, contents: &str) -> String {
+            let path = self.0.join(name);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).unwrap();
+            }
+            std::fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // Frames as a BPQ multi-drop TNC puts them on the wire: the XOR of the
+    // unescaped frame follows it, escaped like any other byte
+    #[test]
+    fn xkiss_checksum_matches_bpq_frames() {
+        // UI frame N0CALL>APRS "Hi" on address 2
+        let ui = [0x20, 0x82, 0xA0, 0xA4, 0xA6, 0x40, 0x40, 0x60, 0x9C, 0x60, 0x86, 0x82, 0x98, 0x98,
+                  0x61, 0x03, 0xF0, 0x48, 0x69];
+        assert_eq!(calculate_xkiss_checksum(&ui), 0x2B);
+        assert!(verify_xkiss_checksum(&[&ui[..], &[0x2B]].concat()));
+        // The additive sum used before is not what the TNC sends
+        assert!(!verify_xkiss_checksum(&[&ui[..], &[0xA5]].concat()));
+
+        // Poll of address 1
+        assert_eq!(kiss::encode(&[0x1E, calculate_xkiss_checksum(&[0x1E])]), [0xC0, 0x1E, 0x1E, 0xC0]);
+        // A checksum of FEND is escaped
+        assert_eq!(kiss::encode(&[0x00, 0xC0, calculate_xkiss_checksum(&[0x00, 0xC0])]),
+                   [0xC0, 0x00, 0xDB, 0xDC, 0xDB, 0xDC, 0xC0]);
+    }
+
+    #[test]
+    fn load_reports_both_validation_passes_at_once() {
+        let dir = TempDir::new("both-passes");
+        let path = dir.file("rax25kb.cfg", "\
+cross_connect0000.serial_port=/dev/ttyUSB0
+cross_connect0000.tcp_port=8001
+cross_connect0000.baud_rate=fast
+cross_connect0001.serial_port=/dev/ttyUSB1
+cross_connect0001.tcp_port=8001
+cross_connect0002.tcp_port=8002
+");
+        let Err(error) = Config::from_file(&path) else { panic!("{} should not load", path) };
+        let found: Vec<(usize, &str)> = error.diagnostics.iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect();
+        assert_eq!(found, vec![
+            (3, "cross_connect0000.baud_rate: 'fast' is not a number"),
+            (6, "cross_connect0002 has no serial_port"),
+            (5, "cross_connect0001 listens on 0.0.0.0:8001, already used by cross_connect0000 (0.0.0.0:8001)"),
+        ]);
+    }
+
+    #[test]
+    fn migrated_config_loads_to_the_same_settings() {
+        let dir = TempDir::new("migrate");
+        let settings = "\
+# TNC on the first USB port
+log_level=6
+serial_port=/dev/ttyUSB0
+baud_rate=1200
+tcp_port=8010
+phil_flag=yes
+";
+        let legacy = dir.file("legacy.cfg", &format!("{}xkiss_mode=yes\n", settings));
+        let (migrated, warnings) = config::migrate_legacy(&legacy).unwrap();
+        let messages: Vec<(usize, &str)> = warnings.iter().map(|d| (d.line, d.message.as_str())).collect();
+        assert_eq!(messages, vec![
+            (7, "xkiss_mode was ignored by the legacy format; commented out as cross_connect0000.xkiss_mode"),
+        ]);
+
+        assert!(migrated.contains("\ncross_connect0000.serial_port=/dev/ttyUSB0\n"), "{}", migrated);
+
+        // The legacy file without the key it could not use
+        let expected = Config::from_file(&dir.file("expected.cfg", settings)).unwrap();
+        let loaded = Config::from_file(&dir.file("migrated.cfg", &migrated)).unwrap();
+        assert_eq!(config::print_config(&loaded), config::print_config(&expected), "{}", migrated);
+    }
+
+    fn cross_connect(settings: &[(&str, &str)]) -> CrossConnect {
+        let entries: Vec<config::Entry> = [("serial_port", "/dev/ttyUSB0")].iter().chain(settings)
+            .map(|&(param, value)| config::Entry {
+                key: format!("cross_connect0000.{}", param),
+                value: value.to_string(),
+                file: "test.cfg".to_string(),
+                line: 1,
+                secret: None,
+            })
+            .collect();
+        Config::from_entries(&entries).cross_connects.remove(0)
+    }
+
+    const ALL_PARAMS: [(&str, &str); 6] = [("kiss_port", "2"), ("txdelay", "30"), ("persist", "63"),
+                                           ("slottime", "10"), ("txtail", "5"), ("fullduplex", "yes")];
+
+    #[test]
+    fn managed_params_are_sent_on_the_cross_connects_port() {
+        let params = ManagedParams::new(&cross_connect(&ALL_PARAMS)).unwrap();
+        assert_eq!(params.frames, [
+            0xC0, 0x21, 30, 0xC0,
+            0xC0, 0x22, 63, 0xC0,
+            0xC0, 0x23, 10, 0xC0,
+            0xC0, 0x24, 5, 0xC0,
+            0xC0, 0x25, 1, 0xC0,
+        ]);
+        assert_eq!(params.summary, "TXDELAY 30, PERSIST 63, SLOTTIME 10, TXTAIL 5, FULLDUPLEX 1");
+
+        // Only what is set, and on an XKISS line with its address and checksum
+        let config = cross_connect(&[("xkiss_mode", "yes"), ("xkiss_port", "3"), ("xkiss_checksum", "yes"),
+                                     ("txdelay", "30")]);
+        let params = ManagedParams::new(&config).unwrap();
+        assert_eq!(params.frames, [0xC0, 0x31, 30, 0x31 ^ 30, 0xC0]);
+        assert!(ManagedParams::new(&cross_connect(&[])).is_none());
+    }
+
+    #[test]
+    fn reopened_device_gets_every_managed_param_again() {
+        let config = cross_connect(&ALL_PARAMS);
+        let params = ManagedParams::new(&config).unwrap();
+        let expected = params.frames.clone();
+        let (sender, _receiver) = mpsc::channel();
+        let routes = Mutex::new(vec![DemuxRoute {
+            id: config.id.clone(),
+            owner: config.slot.clone(),
+            kiss_port: config.wire_port(),
+            is_primary: true,
+            sender,
+            stats: Arc::new(FrameStats::default()),
+            poll_timeout: None,
+            checksum: false,
+            params: Some(params),
+        }]);
+        let (queue, jobs) = mpsc::sync_channel(WRITE_QUEUE_LEN);
+        let logger = Logger::new(None, 0, false).unwrap();
+
+        // Nothing is queued while the device is lost, all of it once the
+        // reader has reopened it
+        let state = Mutex::new(DeviceState::Lost { since: Instant::now(), error: "unplugged".to_string() });
+        SerialDemux::send_params(&state, &queue, &routes, "/dev/ttyUSB0", |_| true, 6, &logger);
+        assert!(jobs.try_recv().is_err());
+        *state.lock().unwrap() = DeviceState::Open;
+        SerialDemux::send_params(&state, &queue, &routes, "/dev/ttyUSB0", |_| true, 6, &logger);
+        match jobs.try_recv() {
+            Ok(WriteJob::Frame(data, None)) => assert_eq!(data, expected),
+            _ => panic!("KISS parameters not queued"),
+        }
+    }
+}
@@ -0,0 +1,652 @@
+// rax25kb - AX.25 KISS Bridge
+//
+// Copyright (C) 2025-2026 Kris Kirby, KE4AHR
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+//
+// Frame processing between the endpoints of a cross-connect
+//
+// This file is part of rax25kb.
+//
+// rax25kb is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rax25kb is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rax25kb.  If not, see <https://www.gnu.org/licenses/>.
+//
+// A cross-connect links its device (endpoint 0, always a TNC) with one or
+// more far endpoints. A far endpoint is either a host (TCP server, TCP
+// client, pty), which speaks KISS as an application does, or another TNC
+// (the far device of a serial-to-serial link). Units from the device go to
+// every host, or are translated for the far TNC; units from a host or the
+// far TNC go to the device. The pipeline does not care which transport an
+// endpoint is, so any mix of them runs through the same code:
+//
+//   TNC -> hosts   PhilFlag RX fix, decode and checksum, dump/parse/PCAP,
+//                  kiss_chan filter and remap, ACKMODE ack routing, XKISS
+//                  emulation queue, AGW monitor
+//   host -> TNC    decode, XKISS emulation (checksum, host polls), kiss_chan
+//                  remap, KISSCOPY, ACKMODE, encode for the TNC
+//   TNC -> TNC     PhilFlag RX fix, decode and checksum, dump/parse/PCAP,
+//                  KISS/XKISS port translation, encode for the far TNC
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::firewall::{self, Action, Filter};
+use crate::transport::{ConnId, Event, Outgoing, Sink, Target, Transport};
+use crate::{
+    encode_for_serial, host_frame, kiss, parse_kiss_frame, process_frame_with_phil_flag,
+    send_to_agw_clients, verify_xkiss_checksum, AgwClientInfo, CrossConnect, DrainHook,
+    FrameError, FrameStats, Logger, ParamPolicy, PcapWriter, XkissRxBuffer,
+};
+
+// ACKMODE sequences remembered per connection before the oldest is forgotten
+const MAX_PENDING_ACKS: usize = 256;
+
+// A connection of one endpoint: (endpoint index, connection)
+type Connection = (usize, ConnId);
+
+// ACKMODE (port, sequence) sent to the TNC
+type PendingAck = (u8, [u8; 2]);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Tnc,     // Serial KISS/XKISS TNC
+    Host,    // Host application speaking KISS to us
+}
+
+pub struct Endpoint {
+    pub transport: Arc<dyn Transport>,
+    pub config: CrossConnect,    // KISS/XKISS mode, checksum and PhilFlag of this side
+    pub role: Role,
+}
+
+pub struct Pipeline {
+    config: CrossConnect,
+    endpoints: Vec<Endpoint>,    // [0] is the device, the rest its far endpoints
+    stats: Arc<FrameStats>,
+    xkiss_buffer: Mutex<XkissRxBuffer>,
+    pending_acks: Mutex<BTreeMap<Connection, Vec<PendingAck>>>,  // Oldest first, per connection
+    filter: Filter,              // KISS command firewall for frames from hosts
+    agw_clients: Arc<Mutex<Vec<Option<AgwClientInfo>>>>,
+    logger: Arc<Logger>,
+    pcap: Option<Arc<PcapWriter>>,
+}
+
+impl Pipeline {
+    pub fn new(config: CrossConnect, endpoints: Vec<Endpoint>, stats: Arc<FrameStats>,
+               agw_clients: Arc<Mutex<Vec<Option<AgwClientInfo>>>>, logger: Arc<Logger>,
+               pcap: Option<Arc<PcapWriter>>) -> Self {
+        Pipeline {
+            xkiss_buffer: Mutex::new(XkissRxBuffer::new(config.xkiss_rx_buffer_size)),
+            filter: Filter::parse(config.kiss_command_filter.as_deref(), config.kiss_client_filter.as_deref())
+                .unwrap_or_default(),
+            config,
+            endpoints,
+            stats,
+            pending_acks: Mutex::new(BTreeMap::new()),
+            agw_clients,
+            logger,
+            pcap,
+        }
+    }
+
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn device(&self) -> &Arc<dyn Transport> {
+        &self.endpoints[0].transport
+    }
+
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    // Where the transport of endpoint `index` delivers its events
+    pub fn sink(self: &Arc<Self>, index: usize) -> Sink {
+        let pipeline = self.clone();
+        Arc::new(move |event| pipeline.handle(index, event))
+    }
+
+    pub fn handle(&self, from: usize, event: Event) {
+        match event {
+            // A new connection in a slot knows nothing of its predecessor's frames
+            Event::Connected(conn) | Event::Disconnected(conn) => {
+                self.pending_acks.lock().unwrap().remove(&(from, conn));
+            }
+            Event::Received(conn, unit) => {
+                let far_tnc = self.endpoints.iter().skip(1).position(|endpoint| endpoint.role == Role::Tnc);
+                match (from, self.endpoints[from].role, far_tnc) {
+                    (0, _, Some(peer)) => self.tnc_to_tnc(0, peer + 1, unit),
+                    (0, _, None) => self.tnc_to_hosts(unit),
+                    (_, Role::Tnc, _) => self.tnc_to_tnc(from, 0, unit),
+                    (_, Role::Host, _) => self.host_to_tnc(from, conn, unit),
+                }
+            }
+        }
+    }
+
+    fn log_send_error(&self, index: usize, conn: ConnId, result: std::io::Result<()>) {
+        if let Err(e) = result {
+            let transport = &self.endpoints[index].transport;
+            self.logger.log(&format!("[{}] {} write error on {}: {}",
+                self.config.id, transport.connection(conn), transport.describe(), e), 4);
+        }
+    }
+
+    // Send to every host endpoint, except connection `except` (KISSCOPY)
+    fn to_hosts(&self, except: Option<Connection>, data: &[u8]) {
+        for (index, endpoint) in self.endpoints.iter().enumerate().skip(1) {
+            if endpoint.role != Role::Host {
+                continue;
+            }
+            let to = match except {
+                Some((from, conn)) if from == index => Target::AllExcept(conn),
+                _ => Target::All,
+            };
+            self.log_send_error(index, 0, endpoint.transport.send(to, Outgoing::raw(data)));
+        }
+    }
+
+    fn to_host(&self, index: usize, conn: ConnId, data: &[u8]) {
+        self.log_send_error(index, conn,
+            self.endpoints[index].transport.send(Target::Only(conn), Outgoing::raw(data)));
+    }
+
+    // A unit heard on a TNC, decoded; None if it was dropped. `direction`
+    // labels the frame dump.
+    fn decode_from_tnc(&self, config: &CrossConnect, unit: Vec<u8>, direction: &str) -> Option<Vec<u8>> {
+        let processed = if config.phil_flag {
+            process_frame_with_phil_flag(&unit)
+        } else {
+            unit
+        };
+        let decoded = decode_serial_frame(&processed, &self.config.id,
+            config.xkiss_mode && config.xkiss_checksum, &self.stats, &self.logger)?;
+        if config.dump_frames {
+            self.logger.log(&format!("[{}] {} ({} bytes): {:02x?}",
+                self.config.id, direction, decoded.len(), decoded), 7);
+        }
+        if config.parse_kiss {
+            parse_kiss_frame(&decoded, &self.logger, &self.config.id);
+        }
+        if let Some(ref pcap) = self.pcap {
+            pcap.write_kiss_frame(&decoded);
+        }
+        Some(decoded)
+    }
+
+    // Frames from the device go to the host endpoints (or the XKISS emulation
+    // queue), AGW clients and PCAP
+    fn tnc_to_hosts(&self, unit: Vec<u8>) {
+        let config = &self.config;
+        if config.raw_copy {
+            // Raw copy mode: send to all clients
+            self.to_hosts(None, &unit);
+            return;
+        }
+
+        let Some(decoded) = self.decode_from_tnc(config, unit, "Serial->Out") else { return };
+
+        // Check channel filtering and apply channel remapping
+        if config.kiss_chan != -1 && kiss::port(decoded[0]) as i32 != config.kiss_chan {
+            return;
+        }
+        let wire_port = kiss::port(decoded[0]);
+
+        // Apply channel remapping if needed (Serial -> TCP)
+        let app_frame = if config.kiss_chan >= 0 && config.kiss_chan <= 15 {
+            // Remap channel to 0 for application
+            remap_kiss_channel_in(&decoded, 0)
+        } else {
+            decoded
+        };
+
+        // ACKMODE ack from the TNC: only the connection that sent the frame gets it
+        if kiss::command(app_frame[0]) == kiss::CMD_ACKMODE && app_frame.len() == 3 {
+            self.deliver_ack(wire_port, [app_frame[1], app_frame[2]], &host_frame(&app_frame, config));
+            return;
+        }
+
+        if config.xkiss_emulation {
+            // Emulated XKISS TNC: hold the frame until the host polls its address
+            let mut buffer = self.xkiss_buffer.lock().unwrap();
+            match buffer.push(app_frame.clone()) {
+                Ok(_) => {
+                    if config.dump_frames {
+                        self.logger.log(&format!("[{}] Queued frame for host poll ({} bytes, queue has {})",
+                            config.id, app_frame.len(), buffer.len()), 7);
+                    }
+                }
+                Err(e) => {
+                    self.logger.log(&format!("[{}] Buffer full, dropping packet: {}", config.id, e), 4);
+                }
+            }
+        } else {
+            self.to_hosts(None, &kiss::encode(&app_frame));
+        }
+
+        if config.agw_enable {
+            send_to_agw_clients(&app_frame, &self.agw_clients, config.agw_port, false,
+                &self.logger, &config.id);
+        }
+    }
+
+    // Frames from connection `conn` of host endpoint `from` go to the device
+    fn host_to_tnc(&self, from: usize, conn: ConnId, unit: Vec<u8>) {
+        let config = &self.config;
+        let who = self.endpoints[from].transport.connection(conn);
+        if config.dump_frames {
+            self.logger.log(&format!("[{}] {}->Serial ({} bytes): {:02x?}",
+                config.id, who, unit.len(), unit), 7);
+        }
+
+        // Raw copy (and unvalidated TCP-to-TCP) pass bytes through unframed
+        if config.raw_copy || (config.tcp_to_tcp_dangerous && config.tcp_to_tcp_also_dangerous) {
+            if config.kiss_copy {
+                self.to_hosts(Some((from, conn)), &unit);
+            }
+            self.log_send_error(0, 0, self.device().send(Target::All, Outgoing::raw(&unit)));
+            return;
+        }
+
+        let Some(mut decoded) = decode_frame(&unit, &config.id, &self.logger) else { return };
+
+        // Host side of an emulated XKISS TNC: check the checksum and answer
+        // polls here instead of passing them to the serial port
+        if config.xkiss_emulation {
+            if config.xkiss_checksum {
+                if !verify_xkiss_checksum(&decoded) {
+                    self.logger.log(&format!("[{}] {}: XKISS checksum failed, frame dropped: {:02x?}",
+                        config.id, who, decoded), 4);
+                    return;
+                }
+                decoded.pop();
+            }
+            if kiss::command(decoded[0]) == kiss::CMD_POLL {
+                self.answer_host_poll(from, conn, kiss::port(decoded[0]));
+                return;
+            }
+        }
+
+        // KISS command firewall
+        if !self.filter.is_empty() {
+            let client = self.endpoints[from].transport.client(conn);
+            let name = firewall::command_name(decoded[0]);
+            let from_who = match client {
+                Some(client) => format!("{} ({})", who, client),
+                None => who.clone(),
+            };
+            match self.filter.verdict(client, decoded[0]) {
+                Action::Allow => {}
+                Action::Drop => {
+                    self.logger.log(&format!("[{}] {}: {} dropped by KISS command filter",
+                        config.id, from_who, name), 5);
+                    return;
+                }
+                Action::Rewrite(value) if decoded.len() == 2 && decoded[1] != value => {
+                    self.logger.log(&format!("[{}] {}: {} {} rewritten to {}",
+                        config.id, from_who, name, decoded[1], value), 6);
+                    decoded[1] = value;
+                }
+                Action::Rewrite(_) => {}
+            }
+        }
+
+        // Apply channel remapping if needed (TCP -> Serial)
+        let app_command = decoded[0];
+        let mut remapped = if config.kiss_chan >= 0 && config.kiss_chan <= 15 {
+            // Remap KISS channel 0 back to configured channel
+            remap_kiss_channel_in(&decoded, config.kiss_port)
+        } else {
+            decoded
+        };
+        let port = kiss::port(remapped[0]);
+
+        // A KISS parameter this cross-connect manages for its port
+        let command = kiss::command(remapped[0]);
+        let managed = config.kiss_param(command).filter(|_| port == config.wire_port() && remapped.len() == 2);
+        if let (Some(value), Some(name)) = (managed, kiss::param_name(command)) {
+            match config.kiss_param_policy {
+                ParamPolicy::Pass => {}
+                ParamPolicy::Block => {
+                    self.logger.log(&format!("[{}] {}: {} {} blocked, managed at {}",
+                        config.id, who, name, remapped[1], value), 6);
+                    return;
+                }
+                ParamPolicy::Override if remapped[1] != value => {
+                    self.logger.log(&format!("[{}] {}: {} {} overridden with {}",
+                        config.id, who, name, remapped[1], value), 6);
+                    remapped[1] = value;
+                }
+                ParamPolicy::Override => {}
+            }
+        }
+
+        // KISSCOPY: Send to other clients (as plain KISS, without TNC-side checksum or PhilFlag)
+        if config.kiss_copy {
+            self.to_hosts(Some((from, conn)), &kiss::encode(&remapped));
+        }
+
+        // ACKMODE: [cmd][seq hi][seq lo][AX.25]. Either emulate the ack once the
+        // frame has drained, or pass it to the TNC and remember who to ack.
+        let mut on_sent: Option<DrainHook> = None;
+        if kiss::command(remapped[0]) == kiss::CMD_ACKMODE && remapped.len() >= 3 {
+            let seq = [remapped[1], remapped[2]];
+            if config.ackmode_emulation {
+                let ack = host_frame(&[app_command, seq[0], seq[1]], config);
+                let transport = self.endpoints[from].transport.clone();
+                let id = config.id.clone();
+                let logger = self.logger.clone();
+                on_sent = Some(Box::new(move || {
+                    if let Err(e) = transport.send(Target::Only(conn), Outgoing::raw(&ack)) {
+                        logger.log(&format!("[{}] {} write error on {}: {}",
+                            id, transport.connection(conn), transport.describe(), e), 4);
+                    }
+                }));
+                remapped.drain(1..3);
+                remapped[0] = port << 4;  // Plain data frame for the TNC
+            } else {
+                self.expect_ack(from, conn, port, seq);
+            }
+        }
+
+        // One whole frame per send, so connections never interleave on the line
+        let processed = encode_for_serial(&remapped, config);
+        self.log_send_error(0, 0, self.device().send(Target::All,
+            Outgoing { data: &processed, port: Some(port), on_sent }));
+    }
+
+    // Frames heard on TNC endpoint `from` are translated for TNC endpoint `to`
+    fn tnc_to_tnc(&self, from: usize, to: usize, unit: Vec<u8>) {
+        let source = &self.endpoints[from];
+        let target = &self.endpoints[to];
+        let (from_config, to_config) = (&source.config, &target.config);
+
+        if from_config.raw_copy || to_config.raw_copy {
+            // Raw copy mode: no framing, pass bytes straight through
+            self.log_send_error(to, 0, target.transport.send(Target::All, Outgoing::raw(&unit)));
+            return;
+        }
+
+        let direction = format!("{}->{}", source.transport.describe(), target.transport.describe());
+        let Some(decoded) = self.decode_from_tnc(from_config, unit, &direction) else { return };
+
+        let translated = translate_kiss_port(&decoded, from_config, to_config);
+        // XKISS checksum and PhilFlag TX correction for the side the frame is sent to
+        let outgoing = encode_for_serial(&translated, to_config);
+        self.log_send_error(to, 0, target.transport.send(Target::All,
+            Outgoing::frame(&outgoing, kiss::port(translated[0]))));
+    }
+
+    // Remember that connection `conn` of endpoint `from` is waiting for the
+    // TNC's ack of ACKMODE `seq`
+    fn expect_ack(&self, from: usize, conn: ConnId, port: u8, seq: [u8; 2]) {
+        let mut pending_acks = self.pending_acks.lock().unwrap();
+        let pending = pending_acks.entry((from, conn)).or_default();
+        if pending.len() >= MAX_PENDING_ACKS {
+            pending.remove(0);
+        }
+        pending.push((port, seq));
+    }
+
+    // Send an ACKMODE ack from the TNC to the connection that sent the frame.
+    // Acks nobody is waiting for are dropped, never broadcast.
+    fn deliver_ack(&self, port: u8, seq: [u8; 2], ack: &[u8]) {
+        let owner = self.pending_acks.lock().unwrap().iter_mut().find_map(|(&owner, pending)| {
+            let position = pending.iter().position(|&entry| entry == (port, seq))?;
+            pending.remove(position);
+            Some(owner)
+        });
+        match owner {
+            Some((index, conn)) => self.to_host(index, conn, ack),
+            None => self.logger.log(&format!("[{}] ACKMODE ack {:02x}{:02x} on port {} has no waiting client, dropped",
+                self.config.id, seq[0], seq[1], port), 7),
+        }
+    }
+
+    // Answer a host poll for `address` with the oldest frame queued for it,
+    // or echo the poll when there is none. Only the polling connection is answered.
+    fn answer_host_poll(&self, from: usize, conn: ConnId, address: u8) {
+        let reply = self.xkiss_buffer.lock().unwrap().pop_for(address)
+            .unwrap_or_else(|| vec![(address << 4) | kiss::CMD_POLL]);
+        self.to_host(from, conn, &host_frame(&reply, &self.config));
+    }
+}
+
+// Undo KISS escaping on a deframed frame; malformed frames are logged and dropped
+fn decode_frame(frame: &[u8], bridge_id: &str, logger: &Logger) -> Option<Vec<u8>> {
+    match kiss::decode(frame) {
+        Ok(decoded) => Some(decoded),
+        Err(e) => {
+            logger.log(&format!("[{}] Malformed KISS frame dropped: {} ({} bytes): {:02x?}",
+                bridge_id, e, frame.len(), frame), 4);
+            None
+        }
+    }
+}
+
+// Decode a frame heard on serial, verifying and stripping the XKISS checksum
+// if `checksum` is set; bad escapes, checksum failures and runts are counted
+// against this cross-connect
+fn decode_serial_frame(frame: &[u8], bridge_id: &str, checksum: bool, stats: &FrameStats,
+                       logger: &Logger) -> Option<Vec<u8>> {
+    let mut decoded = match kiss::decode(frame) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            stats.record(FrameError::BadEscape, bridge_id, &format!(
+                "Malformed KISS frame dropped: {} ({} bytes): {:02x?}", e, frame.len(), frame), logger);
+            return None;
+        }
+    };
+    if checksum {
+        if !verify_xkiss_checksum(&decoded) {
+            stats.record(FrameError::BadChecksum, bridge_id, &format!(
+                "XKISS checksum failed, frame dropped ({} bytes): {:02x?}", decoded.len(), decoded), logger);
+            return None;
+        }
+        decoded.pop();
+    }
+    if kiss::is_runt(&decoded) {
+        stats.record(FrameError::Runt, bridge_id, &format!(
+            "Runt KISS frame dropped ({} bytes): {:02x?}", decoded.len(), decoded), logger);
+        return None;
+    }
+    Some(decoded)
+}
+
+// Helper function to remap KISS channel from app (channel 0) to configured channel
+fn remap_kiss_channel_in(frame: &[u8], target_channel: u8) -> Vec<u8> {
+    let mut result = frame.to_vec();
+    if let Some(kiss_byte) = result.first_mut() {
+        *kiss_byte = (target_channel << 4) | kiss::command(*kiss_byte);
+    }
+    result
+}
+
+// Rewrite the port nibble of a decoded frame between KISS and XKISS addressing:
+// to an XKISS TNC the port becomes its xkiss_port, from one the KISS side's kiss_port
+fn translate_kiss_port(frame: &[u8], from: &CrossConnect, to: &CrossConnect) -> Vec<u8> {
+    let mut result = frame.to_vec();
+    if result.is_empty() || from.xkiss_mode == to.xkiss_mode {
+        return result;
+    }
+
+    let new_port = if to.xkiss_mode {
+        to.xkiss_port
+    } else {
+        from.xkiss_port.map(|_| to.kiss_port)
+    };
+    if let Some(new_port) = new_port {
+        result[0] = ((new_port & 0x0F) << 4) | kiss::command(result[0]);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Receiver;
+
+    use super::*;
+    use crate::transport::memory::{MemoryTransport, Sent};
+    use crate::{config, Config};
+
+    // An AX.25 frame long enough not to be a runt, with bytes that need escaping
+    const AX25: [u8; 16] = [0x96, 0x68, 0x8A, 0x82, 0x90, 0xA4, 0x60, 0xC0,
+                            0xDB, 0x82, 0x84, 0x86, 0x88, 0x8A, 0x61, 0x03];
+
+    fn frame(command_byte: u8) -> Vec<u8> {
+        [command_byte].into_iter().chain(AX25).collect()
+    }
+
+    // What a transport hands the pipeline for an encoded frame: its content between the FENDs
+    fn unit(encoded: &[u8]) -> Vec<u8> {
+        encoded[1..encoded.len() - 1].to_vec()
+    }
+
+    fn cross_connect(settings: &[(&str, &str)]) -> CrossConnect {
+        let entries: Vec<config::Entry> = [("serial_port", "/dev/ttyUSB0")].iter().chain(settings)
+            .map(|&(param, value)| config::Entry {
+                key: format!("cross_connect0000.{}", param),
+                value: value.to_string(),
+                file: "test.cfg".to_string(),
+                line: 1,
+                secret: None,
+            })
+            .collect();
+        Config::from_entries(&entries).cross_connects.remove(0)
+    }
+
+    // A device linked with a host endpoint of two connections, or with the
+    // far TNC `peer`, started the way a bridge starts them
+    struct Harness {
+        device: Arc<MemoryTransport>,
+        far: Arc<MemoryTransport>,
+        to_device: Receiver<Sent>,
+        to_far: Receiver<Sent>,
+    }
+
+    fn harness(config: &CrossConnect, peer: Option<&CrossConnect>) -> Harness {
+        let (device, to_device) = MemoryTransport::new(vec![None]);
+        let (far, to_far) = MemoryTransport::new(vec![None, None]);
+        let endpoints = vec![
+            Endpoint { transport: device.clone(), config: config.clone(), role: Role::Tnc },
+            match peer {
+                Some(peer) => Endpoint { transport: far.clone(), config: peer.clone(), role: Role::Tnc },
+                None => Endpoint { transport: far.clone(), config: config.clone(), role: Role::Host },
+            },
+        ];
+        let pipeline = Arc::new(Pipeline::new(config.clone(), endpoints, Arc::new(FrameStats::default()),
+            Arc::new(Mutex::new(Vec::new())), Arc::new(Logger::new(None, 0, false).unwrap()), None));
+        device.start(pipeline.sink(0)).unwrap();
+        far.start(pipeline.sink(1)).unwrap();
+        Harness { device, far, to_device, to_far }
+    }
+
+    #[test]
+    fn tnc_to_hosts_filters_and_remaps_kiss_chan() {
+        let config = cross_connect(&[("kiss_port", "2"), ("kiss_chan", "2")]);
+        let h = harness(&config, None);
+
+        h.device.deliver(Event::Received(0, unit(&kiss::encode(&frame(0x20)))));
+        assert_eq!(h.to_far.try_recv(), Ok((Target::All, kiss::encode(&frame(0x00)), None)));
+        h.device.deliver(Event::Received(0, unit(&kiss::encode(&frame(0x10)))));
+        assert!(h.to_far.try_recv().is_err());
+
+        // And back to the configured channel on the way to the TNC
+        h.far.deliver(Event::Received(0, unit(&kiss::encode(&frame(0x00)))));
+        assert_eq!(h.to_device.try_recv(), Ok((Target::All, kiss::encode(&frame(0x20)), Some(2))));
+    }
+
+    #[test]
+    fn host_to_tnc_copies_to_other_connections() {
+        let h = harness(&cross_connect(&[("kiss_copy", "yes")]), None);
+
+        h.far.deliver(Event::Received(1, unit(&kiss::encode(&frame(0x00)))));
+        assert_eq!(h.to_far.try_recv(), Ok((Target::AllExcept(1), kiss::encode(&frame(0x00)), None)));
+        assert_eq!(h.to_device.try_recv(), Ok((Target::All, kiss::encode(&frame(0x00)), Some(0))));
+        assert!(h.to_far.try_recv().is_err());
+    }
+
+    #[test]
+    fn ackmode_ack_goes_to_the_sender_only() {
+        let h = harness(&cross_connect(&[]), None);
+        let ackmode: Vec<u8> = [0x0C, 0x12, 0x34].into_iter().chain(AX25).collect();
+        let ack = [0x0C, 0x12, 0x34];
+
+        h.far.deliver(Event::Received(1, unit(&kiss::encode(&ackmode))));
+        assert_eq!(h.to_device.try_recv(), Ok((Target::All, kiss::encode(&ackmode), Some(0))));
+        h.device.deliver(Event::Received(0, unit(&kiss::encode(&ack))));
+        assert_eq!(h.to_far.try_recv(), Ok((Target::Only(1), kiss::encode(&ack), None)));
+
+        // Acks nobody is waiting for are dropped
+        h.device.deliver(Event::Received(0, unit(&kiss::encode(&ack))));
+        h.device.deliver(Event::Received(0, unit(&kiss::encode(&[0x0C, 0x56, 0x78]))));
+        assert!(h.to_far.try_recv().is_err());
+
+        // A reconnected slot does not get its predecessor's acks
+        h.far.deliver(Event::Received(1, unit(&kiss::encode(&ackmode))));
+        h.far.deliver(Event::Disconnected(1));
+        h.device.deliver(Event::Received(0, unit(&kiss::encode(&ack))));
+        assert!(h.to_far.try_recv().is_err());
+    }
+
+    #[test]
+    fn ackmode_emulation_acks_once_sent() {
+        let h = harness(&cross_connect(&[("ackmode_emulation", "yes")]), None);
+        let ackmode: Vec<u8> = [0x0C, 0x12, 0x34].into_iter().chain(AX25).collect();
+
+        h.far.deliver(Event::Received(1, unit(&kiss::encode(&ackmode))));
+        assert_eq!(h.to_device.try_recv(), Ok((Target::All, kiss::encode(&frame(0x00)), Some(0))));
+        assert_eq!(h.to_far.try_recv(), Ok((Target::Only(1), kiss::encode(&[0x0C, 0x12, 0x34]), None)));
+    }
+
+    #[test]
+    fn xkiss_emulation_answers_polls() {
+        for checksum in ["no", "yes"] {
+            let config = cross_connect(&[("xkiss_emulation", "yes"), ("xkiss_checksum", checksum)]);
+            let h = harness(&config, None);
+            let poll = unit(&host_frame(&[0x0E], &config));
+
+            // Held until a host polls, then sent only to the connection that polled
+            h.device.deliver(Event::Received(0, unit(&kiss::encode(&frame(0x00)))));
+            assert!(h.to_far.try_recv().is_err());
+            h.far.deliver(Event::Received(1, poll.clone()));
+            assert_eq!(h.to_far.try_recv(), Ok((Target::Only(1), host_frame(&frame(0x00), &config), None)));
+
+            // An empty queue echoes the poll; polls never reach the TNC
+            h.far.deliver(Event::Received(0, poll));
+            assert_eq!(h.to_far.try_recv(), Ok((Target::Only(0), host_frame(&[0x0E], &config), None)));
+            assert!(h.to_device.try_recv().is_err(), "checksum={}", checksum);
+        }
+    }
+
+    #[test]
+    fn tnc_to_tnc_translates_ports() {
+        let config = cross_connect(&[]);
+        let peer = cross_connect(&[("xkiss_mode", "yes"), ("xkiss_port", "3"), ("xkiss_checksum", "yes")]);
+        let h = harness(&config, Some(&peer));
+        let xkiss = encode_for_serial(&frame(0x30), &peer);
+
+        h.device.deliver(Event::Received(0, unit(&kiss::encode(&frame(0x00)))));
+        assert_eq!(h.to_far.try_recv(), Ok((Target::All, xkiss.clone(), Some(3))));
+        h.far.deliver(Event::Received(0, unit(&xkiss)));
+        assert_eq!(h.to_device.try_recv(), Ok((Target::All, kiss::encode(&frame(0x00)), Some(0))));
+
+        // A bad checksum from the XKISS side is dropped
+        let mut corrupt = unit(&xkiss);
+        corrupt[1] ^= 0x01;
+        h.far.deliver(Event::Received(0, corrupt));
+        assert!(h.to_device.try_recv().is_err());
+    }
+}
@@ -0,0 +1,207 @@
+// rax25kb - AX.25 KISS Bridge
+//
+// Copyright (C) 2025-2026 Kris Kirby, KE4AHR
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+//
+// Serial device selection
+//
+// This file is part of rax25kb.
+//
+// rax25kb is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rax25kb is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rax25kb.  If not, see <https://www.gnu.org/licenses/>.
+//
+// USB-serial adapters are numbered in the order the kernel finds them, so
+// /dev/ttyUSB0 and /dev/ttyUSB1 can swap after a reboot. serial_port (and
+// serial_to_serial) may name a device by what it is instead:
+//
+//   usb:0403:6001              the one USB device with this VID:PID
+//   usb:0403:6001:SN=A1B2C3    ... with this serial number
+//   by-id:usb-FTDI_FT232R_USB_UART_A1B2C3-if00-port0
+//                              a link in /dev/serial/by-id (or a full path)
+//
+// Anything else is a device path. A selector is resolved every time the
+// device is opened, including reopens after it was unplugged.
+
+use std::path::{Path, PathBuf};
+
+const BY_ID_DIR: &str = "/dev/serial/by-id";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+    Path(String),
+    Usb { vid: u16, pid: u16, serial: Option<String> },
+    ById(String),
+}
+
+pub fn parse(spec: &str) -> Result<Selector, String> {
+    if let Some(rest) = spec.strip_prefix("usb:") {
+        let mut fields = rest.splitn(3, ':');
+        let hex = |field: Option<&str>, what: &str| field
+            .filter(|field| field.len() == 4)
+            .and_then(|field| u16::from_str_radix(field, 16).ok())
+            .ok_or_else(|| format!("'{}': {} must be 4 hex digits (usb:VID:PID[:SN=serial])", spec, what));
+        let vid = hex(fields.next(), "the vendor ID")?;
+        let pid = hex(fields.next(), "the product ID")?;
+        let serial = match fields.next() {
+            None => None,
+            Some(field) => match field.strip_prefix("SN=") {
+                Some(serial) if !serial.is_empty() => Some(serial.to_string()),
+                _ => return Err(format!("'{}': expected SN=serial after the product ID", spec)),
+            },
+        };
+        return Ok(Selector::Usb { vid, pid, serial });
+    }
+    if let Some(name) = spec.strip_prefix("by-id:") {
+        if name.is_empty() {
+            return Err(format!("'{}': missing the name of a link in {}", spec, BY_ID_DIR));
+        }
+        return Ok(Selector::ById(name.to_string()));
+    }
+    Ok(Selector::Path(spec.to_string()))
+}
+
+// The device path `spec` stands for right now
+pub fn resolve(spec: &str) -> Result<String, String> {
+    match parse(spec)? {
+        Selector::Path(path) => Ok(path),
+        Selector::ById(name) => {
+            let link = if name.starts_with('/') { PathBuf::from(&name) } else { Path::new(BY_ID_DIR).join(&name) };
+            std::fs::canonicalize(&link)
+                .map(|device| device.to_string_lossy().into_owned())
+                .map_err(|e| format!("{}: {}", link.display(), e))
+        }
+        Selector::Usb { vid, pid, serial } => {
+            let ports = serialport::available_ports()
+                .map_err(|e| format!("cannot list serial ports: {}", e))?;
+            let matches: Vec<String> = ports.into_iter()
+                .filter(|port| match &port.port_type {
+                    serialport::SerialPortType::UsbPort(usb) => usb.vid == vid && usb.pid == pid
+                        && (serial.is_none() || usb.serial_number == serial),
+                    _ => false,
+                })
+                .map(|port| port.port_name)
+                .collect();
+            match matches.as_slice() {
+                [device] => Ok(device.clone()),
+                [] => Err("no such USB serial device connected".to_string()),
+                _ => Err(format!("matches {} devices ({}), add SN= to pick one",
+                    matches.len(), matches.join(", "))),
+            }
+        }
+    }
+}
+
+// Links in /dev/serial/by-id that point at `device`
+fn by_id_names(device: &str) -> Vec<String> {
+    let Ok(links) = std::fs::read_dir(BY_ID_DIR) else { return Vec::new() };
+    let device = std::fs::canonicalize(device).unwrap_or_else(|_| PathBuf::from(device));
+    let mut names: Vec<String> = links.flatten()
+        .filter(|link| std::fs::canonicalize(link.path()).is_ok_and(|target| target == device))
+        .map(|link| link.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+// --list-ports: every serial port with the selectors that match it
+pub fn list() -> Result<String, String> {
+    let mut ports = serialport::available_ports().map_err(|e| format!("Cannot list serial ports: {}", e))?;
+    if ports.is_empty() {
+        return Ok("No serial ports found\n".to_string());
+    }
+    ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+
+    let mut output = String::new();
+    for port in ports {
+        output.push_str(&port.port_name);
+        output.push('\n');
+        match &port.port_type {
+            serialport::SerialPortType::UsbPort(usb) => {
+                let description: Vec<&str> = [&usb.manufacturer, &usb.product].into_iter()
+                    .flatten().map(|text| text.as_str()).collect();
+                let mut selector = format!("usb:{:04x}:{:04x}", usb.vid, usb.pid);
+                if let Some(ref serial) = usb.serial_number {
+                    selector.push_str(&format!(":SN={}", serial));
+                }
+                if description.is_empty() {
+                    output.push_str(&format!("    {}\n", selector));
+                } else {
+                    output.push_str(&format!("    {}  ({})\n", selector, description.join(" ")));
+                }
+            }
+            serialport::SerialPortType::PciPort => output.push_str("    PCI serial port\n"),
+            serialport::SerialPortType::BluetoothPort => output.push_str("    Bluetooth serial port\n"),
+            serialport::SerialPortType::Unknown => {}
+        }
+        for name in by_id_names(&port.port_name) {
+            output.push_str(&format!("    by-id:{}\n", name));
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usb(vid: u16, pid: u16, serial: Option<&str>) -> Result<Selector, String> {
+        Ok(Selector::Usb { vid, pid, serial: serial.map(str::to_string) })
+    }
+
+    #[test]
+    fn plain_names_are_paths() {
+        assert_eq!(parse("/dev/ttyUSB0"), Ok(Selector::Path("/dev/ttyUSB0".to_string())));
+        assert_eq!(parse("COM3"), Ok(Selector::Path("COM3".to_string())));
+    }
+
+    #[test]
+    fn usb_ids_are_hex_in_either_case() {
+        assert_eq!(parse("usb:0403:6001"), usb(0x0403, 0x6001, None));
+        assert_eq!(parse("usb:10c4:EA60"), usb(0x10c4, 0xea60, None));
+    }
+
+    #[test]
+    fn serial_number_may_contain_colons() {
+        assert_eq!(parse("usb:0403:6001:SN=A1B2:C3"), usb(0x0403, 0x6001, Some("A1B2:C3")));
+    }
+
+    #[test]
+    fn short_vendor_id_is_rejected() {
+        assert_eq!(parse("usb:403:6001"),
+            Err("'usb:403:6001': the vendor ID must be 4 hex digits (usb:VID:PID[:SN=serial])".to_string()));
+    }
+
+    #[test]
+    fn missing_or_non_hex_product_id_is_rejected() {
+        assert_eq!(parse("usb:0403"),
+            Err("'usb:0403': the product ID must be 4 hex digits (usb:VID:PID[:SN=serial])".to_string()));
+        assert_eq!(parse("usb:0403:60g1"),
+            Err("'usb:0403:60g1': the product ID must be 4 hex digits (usb:VID:PID[:SN=serial])".to_string()));
+    }
+
+    #[test]
+    fn anything_after_the_product_id_must_be_a_serial_number() {
+        assert_eq!(parse("usb:0403:6001:A1B2"),
+            Err("'usb:0403:6001:A1B2': expected SN=serial after the product ID".to_string()));
+        assert_eq!(parse("usb:0403:6001:SN="),
+            Err("'usb:0403:6001:SN=': expected SN=serial after the product ID".to_string()));
+    }
+
+    #[test]
+    fn by_id_needs_a_link_name() {
+        assert_eq!(parse("by-id:usb-FTDI_FT232R-if00-port0"),
+            Ok(Selector::ById("usb-FTDI_FT232R-if00-port0".to_string())));
+        assert_eq!(parse("by-id:"), Err(format!("'by-id:': missing the name of a link in {}", BY_ID_DIR)));
+    }
+}
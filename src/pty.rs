@@ -0,0 +1,184 @@
+// rax25kb - AX.25 KISS Bridge
+//
+// Copyright (C) 2025-2026 Kris Kirby, KE4AHR
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+//
+// Pseudo-terminal endpoints
+//
+// This file is part of rax25kb.
+//
+// rax25kb is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rax25kb is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rax25kb.  If not, see <https://www.gnu.org/licenses/>.
+//
+// A pty gives programs that expect a serial TNC (kissattach, Xastir, YAAC)
+// a device to open. rax25kb keeps the master side for as long as the bridge
+// runs and links the slave to a fixed path, so the application can close and
+// reopen it at will. The line is put in raw mode once; it keeps its settings
+// while the master is open.
+//
+// While no application has the slave open, the master reports a hangup:
+// reads fail and anything written would pile up for the next application, so
+// send() drops frames instead. The slave is opened and closed once when the
+// pty is created, so that holds before the first application too.
+
+use std::ffi::CStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// ptsname() returns a static buffer
+static PTSNAME: Mutex<()> = Mutex::new(());
+
+// What the master side saw while waiting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PtyState {
+    Closed,      // No application has the slave open
+    Readable,    // The application wrote something
+    Idle,        // The slave is open, nothing to read yet
+}
+
+pub struct Pty {
+    master: File,
+    slave: PathBuf,
+    link: PathBuf,
+}
+
+impl Pty {
+    // Create a pty and point `link` at its slave. An existing symlink at
+    // `link` is replaced (a stale one from an earlier run); anything else
+    // there is an error.
+    pub fn open(link: &Path) -> io::Result<Pty> {
+        let master = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            File::from_raw_fd(fd)
+        };
+        let fd = master.as_raw_fd();
+        if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let slave = {
+            let _guard = PTSNAME.lock().unwrap();
+            let name = unsafe { libc::ptsname(fd) };
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            PathBuf::from(unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned())
+        };
+
+        // Raw 8-bit line: no echo of what we send back to us, no line editing
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        drop(OpenOptions::new().read(true).write(true)
+            .custom_flags(libc::O_NOCTTY).open(&slave)?);
+
+        if let Some(dir) = link.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        match fs::symlink_metadata(link) {
+            Ok(meta) if meta.file_type().is_symlink() => fs::remove_file(link)?,
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a symlink", link.display()))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        std::os::unix::fs::symlink(&slave, link)?;
+
+        Ok(Pty { master, slave, link: link.to_path_buf() })
+    }
+
+    pub fn slave(&self) -> &Path {
+        &self.slave
+    }
+
+    fn poll(&self, events: libc::c_short, timeout: Duration) -> io::Result<libc::c_short> {
+        let mut pollfd = libc::pollfd { fd: self.master.as_raw_fd(), events, revents: 0 };
+        let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+            n if n < 0 => Err(io::Error::last_os_error()),
+            _ => Ok(pollfd.revents),
+        }
+    }
+
+    // Wait up to `timeout` for the application to write something
+    pub fn wait(&self, timeout: Duration) -> io::Result<PtyState> {
+        let revents = self.poll(libc::POLLIN, timeout)?;
+        Ok(if revents & libc::POLLHUP != 0 {
+            PtyState::Closed
+        } else if revents & libc::POLLIN != 0 {
+            PtyState::Readable
+        } else {
+            PtyState::Idle
+        })
+    }
+
+    // Read what the application wrote; Ok(0) when it has closed the slave
+    pub fn read(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        match (&self.master).read(buffer) {
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            result => result,
+        }
+    }
+
+    // Write one whole frame to the application. Frames are dropped while no
+    // application has the slave open; one that is not reading at all gets
+    // a timeout rather than blocking the caller.
+    pub fn send(&self, data: &[u8]) -> io::Result<()> {
+        let mut written = 0;
+        while written < data.len() {
+            if self.poll(libc::POLLOUT, Duration::ZERO)? & libc::POLLHUP != 0 {
+                return Ok(());
+            }
+            match (&self.master).write(&data[written..]) {
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if self.poll(libc::POLLOUT, Duration::from_millis(100))? & libc::POLLOUT == 0 {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut,
+                            "application is not reading"));
+                    }
+                }
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Pty {
+    // Remove the link, unless a newer pty has taken it over
+    fn drop(&mut self) {
+        if fs::read_link(&self.link).is_ok_and(|target| target == self.slave) {
+            let _ = fs::remove_file(&self.link);
+        }
+    }
+}
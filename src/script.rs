@@ -0,0 +1,278 @@
+// rax25kb - AX.25 KISS Bridge
+//
+// Copyright (C) 2025-2026 Kris Kirby, KE4AHR
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+//
+// TNC command scripts
+//
+// This file is part of rax25kb.
+//
+// rax25kb is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rax25kb is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rax25kb.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Many TNCs (MFJ-1278, KPC-3, PK-232) power up in command mode and have to be
+// told to switch to KISS; on the way out they should be sent back with
+// C0 FF C0. init_script is written to a serial device each time it is opened
+// or reopened, exit_script before it is closed. A script is text sent as-is,
+// with
+//
+//   \r \n \t \\ \{ \}    carriage return, line feed, tab and literal characters
+//   \xHH                 any byte, e.g. \xC0\xFF\xC0
+//   {delay MS}           wait MS milliseconds
+//   {expect TEXT}        wait until the TNC sends TEXT (escapes allowed)
+//   {timeout MS}         how long later expects wait (default 5000)
+//
+// Line breaks in the value itself are ignored, so a long script read with
+// @file: can be spread over several lines.
+
+use std::time::{Duration, Instant};
+use serialport::SerialPort;
+
+use crate::Logger;
+
+const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_millis(5000);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Send(Vec<u8>),
+    Delay(Duration),
+    Expect(Vec<u8>),
+    Timeout(Duration),
+}
+
+// Decode the escapes in `text`
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some(c @ ('\\' | '{' | '}')) => bytes.push(c as u8),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 => bytes.push(byte),
+                    _ => return Err(format!("\\x{} is not a byte, use two hex digits as in \\xC0", hex)),
+                }
+            }
+            Some(c) => return Err(format!("unknown escape \\{}", c)),
+            None => return Err("script ends with a lone \\".to_string()),
+        }
+    }
+    Ok(bytes)
+}
+
+fn milliseconds(name: &str, argument: &str) -> Result<Duration, String> {
+    argument.trim().parse().map(Duration::from_millis)
+        .map_err(|_| format!("{{{} {}}}: expected a number of milliseconds", name, argument))
+}
+
+pub fn parse(script: &str) -> Result<Vec<Step>, String> {
+    let script: String = script.chars().filter(|&c| c != '\r' && c != '\n').collect();
+    let mut steps = Vec::new();
+    let mut text = String::new();
+    let mut chars = script.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                text.push(c);
+                text.extend(chars.next());
+            }
+            '{' => {
+                let mut directive = String::new();
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            directive.push(c);
+                            directive.extend(chars.next());
+                        }
+                        '}' => {
+                            closed = true;
+                            break;
+                        }
+                        _ => directive.push(c),
+                    }
+                }
+                if !closed {
+                    return Err(format!("{{{} is not closed with }}", directive));
+                }
+                if !text.is_empty() {
+                    steps.push(Step::Send(unescape(&std::mem::take(&mut text))?));
+                }
+                let (name, argument) = directive.split_once(' ').unwrap_or((&directive, ""));
+                steps.push(match name {
+                    "delay" => Step::Delay(milliseconds(name, argument)?),
+                    "timeout" => Step::Timeout(milliseconds(name, argument)?),
+                    "expect" if !argument.is_empty() => Step::Expect(unescape(argument)?),
+                    "expect" => return Err("{expect} needs the text to wait for".to_string()),
+                    _ => return Err(format!("unknown directive {{{}}}, expected delay, expect or timeout", directive)),
+                });
+            }
+            _ => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        steps.push(Step::Send(unescape(&text)?));
+    }
+    Ok(steps)
+}
+
+// Run the script `name` of `device` on its open port. Write errors are
+// returned; an expect that times out stops the script with a warning, since
+// a TNC already in KISS mode will not answer its command prompt.
+pub fn run(name: &str, script: &str, device: &str, port: &mut dyn SerialPort, logger: &Logger)
+    -> std::io::Result<()> {
+    let steps = parse(script).map_err(std::io::Error::other)?;
+    logger.log(&format!("[{}] Running {}", device, name), 6);
+
+    let mut timeout = DEFAULT_EXPECT_TIMEOUT;
+    for step in steps {
+        match step {
+            Step::Send(bytes) => {
+                port.write_all(&bytes)?;
+                port.flush()?;
+                logger.log(&format!("[{}] {}: sent {}", device, name, printable(&bytes)), 7);
+            }
+            Step::Delay(delay) => std::thread::sleep(delay),
+            Step::Timeout(new) => timeout = new,
+            Step::Expect(wanted) => {
+                if !expect(port, &wanted, timeout)? {
+                    logger.log(&format!("[{}] {}: no \"{}\" within {} ms, rest of script skipped",
+                        device, name, printable(&wanted), timeout.as_millis()), 4);
+                    return Ok(());
+                }
+                logger.log(&format!("[{}] {}: got {}", device, name, printable(&wanted)), 7);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Read until `wanted` has been received or `timeout` has passed
+fn expect(port: &mut dyn SerialPort, wanted: &[u8], timeout: Duration) -> std::io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 256];
+    while Instant::now() < deadline {
+        match port.read(&mut buffer) {
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+        if received.windows(wanted.len()).any(|window| window == wanted) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Script bytes for the log, escaped as they would be written in a script
+fn printable(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| match byte {
+        b'\r' => "\\r".to_string(),
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\\' => "\\\\".to_string(),
+        0x20..=0x7E => (byte as char).to_string(),
+        _ => format!("\\x{:02X}", byte),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(text: &[u8]) -> Step {
+        Step::Send(text.to_vec())
+    }
+
+    fn ms(millis: u64) -> Step {
+        Step::Delay(Duration::from_millis(millis))
+    }
+
+    fn error(script: &str) -> String {
+        parse(script).expect_err(script)
+    }
+
+    #[test]
+    fn empty_script_has_no_steps() {
+        assert_eq!(parse(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn escapes_send_control_bytes() {
+        assert_eq!(parse("KISS ON\\r"), Ok(vec![send(b"KISS ON\r")]));
+        assert_eq!(parse("\\{not a directive\\} \\\\ \\t"), Ok(vec![send(b"{not a directive} \\ \t")]));
+        assert_eq!(parse("\\x1b@K\\r{delay 500}\\xC0\\xFF\\xC0"),
+            Ok(vec![send(b"\x1b@K\r"), ms(500), send(b"\xC0\xFF\xC0")]));
+    }
+
+    #[test]
+    fn text_is_sent_as_utf8() {
+        assert_eq!(parse("caf\u{e9}"), Ok(vec![send("caf\u{e9}".as_bytes())]));
+    }
+
+    #[test]
+    fn line_breaks_are_dropped() {
+        // Only escapes send them
+        assert_eq!(parse("INTFACE KISS\\r\r\n{delay 100}\nRESET\\r\n"),
+            Ok(vec![send(b"INTFACE KISS\r"), ms(100), send(b"RESET\r")]));
+    }
+
+    #[test]
+    fn expect_waits_up_to_the_timeout_set_before_it() {
+        assert_eq!(parse("\\r{timeout 2000}{expect cmd:}KISS ON\\r{expect \\}ok\\r\\n}"), Ok(vec![
+            send(b"\r"), Step::Timeout(Duration::from_millis(2000)), Step::Expect(b"cmd:".to_vec()),
+            send(b"KISS ON\r"), Step::Expect(b"}ok\r\n".to_vec()),
+        ]));
+    }
+
+    #[test]
+    fn unknown_escape_is_an_error() {
+        assert_eq!(error("KISS\\q"), "unknown escape \\q");
+        assert_eq!(error("{expect \\q}"), "unknown escape \\q");
+    }
+
+    #[test]
+    fn backslash_at_end_of_script_is_an_error() {
+        assert_eq!(error("KISS\\"), "script ends with a lone \\");
+    }
+
+    #[test]
+    fn hex_escape_needs_two_digits() {
+        assert_eq!(error("KISS\\x"), "\\x is not a byte, use two hex digits as in \\xC0");
+        assert_eq!(error("\\xC"), "\\xC is not a byte, use two hex digits as in \\xC0");
+        assert_eq!(error("\\xZZ"), "\\xZZ is not a byte, use two hex digits as in \\xC0");
+    }
+
+    #[test]
+    fn unclosed_directive_is_an_error() {
+        assert_eq!(error("{delay 500"), "{delay 500 is not closed with }");
+    }
+
+    #[test]
+    fn bad_directives_are_errors() {
+        assert_eq!(error("{delay soon}"), "{delay soon}: expected a number of milliseconds");
+        assert_eq!(error("{expect}"), "{expect} needs the text to wait for");
+        assert_eq!(error("{wait 5}"), "unknown directive {wait 5}, expected delay, expect or timeout");
+    }
+}
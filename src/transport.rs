@@ -0,0 +1,691 @@
+// rax25kb - AX.25 KISS Bridge
+//
+// Copyright (C) 2025-2026 Kris Kirby, KE4AHR
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+//
+// Endpoints of a cross-connect
+//
+// This file is part of rax25kb.
+//
+// rax25kb is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rax25kb is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rax25kb.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Everything a cross-connect links is a Transport: its route on a serial
+// device, a TCP server and its clients, an upstream TCP connection, a pty,
+// or the far device of a serial-to-serial link. A transport only moves
+// units: the content of one KISS frame between FENDs (still escaped), or a
+// chunk of bytes in raw copy mode. It hands what it receives to a Sink,
+// tagged with the connection it came from, and sends encoded units back.
+// KISS ports, PhilFlag, checksums, ACKMODE and KISSCOPY are the pipeline's
+// business (pipeline.rs), so every pair of endpoints shares that code.
+//
+// Transports carrying a byte stream (TCP, pty) deframe it themselves with a
+// StreamFramer; the serial device is deframed by its SerialDemux, which has
+// to see whole frames to route them by KISS port.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::firewall::Client;
+use crate::{kiss, CrossConnect, DrainHook, FrameStats, Logger, SerialDemux, ACCEPT_POLL_INTERVAL};
+
+// One connection of a transport: a TCP server numbers its client slots,
+// the others only ever have connection 0
+pub type ConnId = usize;
+
+pub enum Event {
+    Connected(ConnId),
+    Received(ConnId, Vec<u8>),
+    Disconnected(ConnId),
+}
+
+pub type Sink = Arc<dyn Fn(Event) + Send + Sync>;
+
+// Which connections of a transport a unit goes to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    All,
+    Only(ConnId),
+    AllExcept(ConnId),    // KISSCOPY: everyone but the sender
+}
+
+impl Target {
+    fn includes(self, conn: ConnId) -> bool {
+        match self {
+            Target::All => true,
+            Target::Only(only) => conn == only,
+            Target::AllExcept(except) => conn != except,
+        }
+    }
+}
+
+// An encoded unit on its way out
+pub struct Outgoing<'a> {
+    pub data: &'a [u8],
+    pub port: Option<u8>,             // KISS port of a frame; None for raw bytes
+    pub on_sent: Option<DrainHook>,   // Run once the unit has been sent
+}
+
+impl<'a> Outgoing<'a> {
+    pub fn raw(data: &'a [u8]) -> Self {
+        Outgoing { data, port: None, on_sent: None }
+    }
+
+    pub fn frame(data: &'a [u8], port: u8) -> Self {
+        Outgoing { data, port: Some(port), on_sent: None }
+    }
+}
+
+pub trait Transport: Send + Sync {
+    // Device, address or path, for log messages
+    fn describe(&self) -> String;
+
+    // How log messages name one connection, e.g. "Client 2"
+    fn connection(&self, conn: ConnId) -> String;
+
+    // Who is at the other end of a connection, for the KISS command firewall
+    fn client(&self, conn: ConnId) -> Option<Client>;
+
+    // Start receiving; events go to `sink` until stop()
+    fn start(&self, sink: Sink) -> Result<(), Box<dyn std::error::Error>>;
+
+    // Send one unit. A serial device holds frames for a polled XKISS address
+    // and runs `on_sent` once the frame has left the line; the others run it
+    // straight away. Failures on one of several connections are logged by
+    // the transport, not returned.
+    fn send(&self, to: Target, unit: Outgoing) -> io::Result<()>;
+
+    // Stop receiving and close every connection
+    fn stop(&self);
+}
+
+fn sent(unit: Outgoing) -> io::Result<()> {
+    if let Some(hook) = unit.on_sent {
+        hook();
+    }
+    Ok(())
+}
+
+// Splits the byte stream of one connection into units: whole KISS frames,
+// or the chunks as they come in raw copy and unvalidated TCP-to-TCP mode
+pub struct StreamFramer {
+    deframer: kiss::Deframer,
+    raw: bool,
+    tcp_to_tcp: bool,     // Bytes outside KISS framing are rejected, not skipped
+    max_len: usize,
+}
+
+impl StreamFramer {
+    pub fn new(config: &CrossConnect) -> Self {
+        StreamFramer {
+            deframer: kiss::Deframer::new(config.max_frame_len),
+            raw: config.raw_copy || (config.tcp_to_tcp_dangerous && config.tcp_to_tcp_also_dangerous),
+            tcp_to_tcp: config.tcp_to_tcp_dangerous,
+            max_len: config.max_frame_len,
+        }
+    }
+
+    // `who` prefixes log messages, e.g. "[bridge] Client 0"
+    pub fn push(&mut self, data: &[u8], who: &str, logger: &Logger) -> Vec<Vec<u8>> {
+        if self.raw {
+            return vec![data.to_vec()];
+        }
+        let mut units = Vec::new();
+        for item in self.deframer.push(data) {
+            match item {
+                kiss::Deframed::Frame(frame) => units.push(frame),
+                kiss::Deframed::Oversize { len, .. } => {
+                    logger.log(&format!("{}: oversize KISS frame dropped ({} bytes, max {})",
+                        who, len, self.max_len), 4);
+                }
+                kiss::Deframed::Resync(skipped) if self.tcp_to_tcp => {
+                    logger.log(&format!("{}: non-KISS packet rejected in TCP-to-TCP mode ({} bytes)",
+                        who, skipped), 4);
+                }
+                kiss::Deframed::Resync(skipped) => {
+                    logger.log(&format!("{}: discarded {} bytes outside KISS framing", who, skipped), 7);
+                }
+            }
+        }
+        units
+    }
+}
+
+// Read a TCP connection until it closes, handing its units to `sink`.
+// Returns the reason it ended, for the log.
+fn read_stream(mut stream: TcpStream, conn: ConnId, who: &str, config: &CrossConnect,
+               sink: &Sink, logger: &Logger) -> String {
+    let mut buffer = vec![0u8; 4096];
+    let mut framer = StreamFramer::new(config);
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return "disconnected".to_string(),
+            Ok(n) => {
+                for unit in framer.push(&buffer[..n], who, logger) {
+                    sink(Event::Received(conn, unit));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return format!("read error: {}", e),
+        }
+    }
+}
+
+// This cross-connect's KISS port on a serial device, or the far device of a
+// serial-to-serial link, as registered with the device's SerialDemux
+pub struct SerialRoute {
+    id: String,                // Bridge the route belongs to, for log messages
+    owner: String,             // Slot of that bridge, which owns the route
+    config: CrossConnect,      // Port, XKISS and polling settings of the route
+    demux: Arc<SerialDemux>,
+    stats: Arc<FrameStats>,
+    logger: Arc<Logger>,
+    running: Arc<AtomicBool>,
+}
+
+impl SerialRoute {
+    pub fn new(bridge: &CrossConnect, config: &CrossConnect, demux: Arc<SerialDemux>,
+               stats: Arc<FrameStats>, logger: Arc<Logger>) -> Self {
+        SerialRoute {
+            id: bridge.id.clone(),
+            owner: bridge.slot.clone(),
+            config: config.clone(),
+            demux,
+            stats,
+            logger,
+            running: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl Transport for SerialRoute {
+    fn describe(&self) -> String {
+        self.config.serial_port.clone()
+    }
+
+    fn connection(&self, _conn: ConnId) -> String {
+        "Serial".to_string()
+    }
+
+    fn client(&self, _conn: ConnId) -> Option<Client> {
+        None
+    }
+
+    fn start(&self, sink: Sink) -> Result<(), Box<dyn std::error::Error>> {
+        let frames = self.demux.register(&self.owner, &self.config, self.stats.clone());
+        let id = self.id.clone();
+        let device = self.config.serial_port.clone();
+        let logger = self.logger.clone();
+        let running = self.running.clone();
+        thread::spawn(move || {
+            for frame in frames {
+                sink(Event::Received(0, frame));
+            }
+            if running.load(Ordering::SeqCst) {
+                logger.log(&format!("[{}] Serial demultiplexer for {} closed", id, device), 4);
+            }
+        });
+        Ok(())
+    }
+
+    fn send(&self, _to: Target, unit: Outgoing) -> io::Result<()> {
+        match unit.port {
+            Some(port) => self.demux.write_frame(port, unit.data, unit.on_sent),
+            None => {
+                self.demux.write(unit.data)?;
+                sent(unit)
+            }
+        }
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        self.demux.unregister(&self.owner);
+    }
+}
+
+// Listening socket and up to max_clients connected clients
+pub struct TcpServer {
+    id: String,
+    config: CrossConnect,
+    address: String,
+    clients: Arc<Mutex<Vec<Option<TcpStream>>>>,
+    logger: Arc<Logger>,
+    running: Arc<AtomicBool>,
+    accept: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl TcpServer {
+    pub fn new(config: &CrossConnect, max_clients: usize, logger: Arc<Logger>) -> Self {
+        TcpServer {
+            id: config.id.clone(),
+            config: config.clone(),
+            address: format!("{}:{}", config.tcp_address, config.tcp_port),
+            clients: Arc::new(Mutex::new((0..max_clients).map(|_| None).collect())),
+            logger,
+            running: Arc::new(AtomicBool::new(true)),
+            accept: Mutex::new(None),
+        }
+    }
+}
+
+impl Transport for TcpServer {
+    fn describe(&self) -> String {
+        self.address.clone()
+    }
+
+    fn connection(&self, conn: ConnId) -> String {
+        format!("Client {}", conn)
+    }
+
+    fn client(&self, conn: ConnId) -> Option<Client> {
+        let clients = self.clients.lock().unwrap();
+        let stream = clients.get(conn)?.as_ref()?;
+        stream.peer_addr().ok().map(Client::Address)
+    }
+
+    fn start(&self, sink: Sink) -> Result<(), Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind(&self.address)?;
+        listener.set_nonblocking(true)?;
+        let clients = self.clients.clone();
+        let max_clients = clients.lock().unwrap().len();
+        self.logger.log(&format!("[{}] TCP listener on {} (max {} clients)",
+            self.id, self.address, max_clients), 5);
+
+        let id = self.id.clone();
+        let config = self.config.clone();
+        let logger = self.logger.clone();
+        let running = self.running.clone();
+
+        let handle = thread::spawn(move || {
+            while running.load(Ordering::SeqCst) {
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(ACCEPT_POLL_INTERVAL);
+                        continue;
+                    }
+                    Err(e) => {
+                        logger.log(&format!("[{}] Accept error: {}", id, e), 3);
+                        continue;
+                    }
+                };
+                let _ = stream.set_nonblocking(false);
+                let peer_addr = stream.peer_addr().ok();
+
+                let index = {
+                    let mut clients = clients.lock().unwrap();
+                    let free = clients.iter().position(|client| client.is_none());
+                    if let (Some(index), Ok(clone)) = (free, stream.try_clone()) {
+                        clients[index] = Some(clone);
+                    }
+                    free
+                };
+                let Some(index) = index else {
+                    logger.log(&format!("[{}] Connection refused from {:?} - {} clients already connected",
+                        id, peer_addr, max_clients), 4);
+                    continue;
+                };
+                logger.log(&format!("[{}] Client {} connected from {:?}", id, index, peer_addr), 6);
+                sink(Event::Connected(index));
+
+                let clients = clients.clone();
+                let config = config.clone();
+                let logger = logger.clone();
+                let sink = sink.clone();
+                thread::spawn(move || {
+                    let who = format!("[{}] Client {}", config.id, index);
+                    let reason = read_stream(stream, index, &who, &config, &sink, &logger);
+                    logger.log(&format!("{} {}", who, reason), if reason == "disconnected" { 6 } else { 4 });
+                    // Report the disconnect before the slot can be reused
+                    sink(Event::Disconnected(index));
+                    clients.lock().unwrap()[index] = None;
+                });
+            }
+        });
+        *self.accept.lock().unwrap() = Some(handle);
+        Ok(())
+    }
+
+    fn send(&self, to: Target, unit: Outgoing) -> io::Result<()> {
+        {
+            let clients = self.clients.lock().unwrap();
+            for (index, stream) in clients.iter().enumerate() {
+                let Some(stream) = stream else { continue };
+                if !to.includes(index) {
+                    continue;
+                }
+                if let Err(e) = (&*stream).write_all(unit.data) {
+                    self.logger.log(&format!("[{}] Client {} write error: {}", self.id, index, e), 4);
+                }
+            }
+        }
+        sent(unit)
+    }
+
+    // The accept loop is joined first so its port is free again and nobody
+    // is accepted after the clients have been disconnected
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(handle) = self.accept.lock().unwrap().take() {
+            let _ = handle.join();
+        }
+        for stream in self.clients.lock().unwrap().iter().flatten() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// Upstream connection to a TCP server, reconnected with backoff
+pub struct TcpClient {
+    id: String,
+    config: CrossConnect,
+    address: String,
+    stream: Arc<Mutex<Option<TcpStream>>>,
+    logger: Arc<Logger>,
+    running: Arc<AtomicBool>,
+}
+
+impl TcpClient {
+    pub fn new(config: &CrossConnect, logger: Arc<Logger>) -> Result<Self, Box<dyn std::error::Error>> {
+        let server_addr = config.tcp_server_address.as_ref()
+            .ok_or("Missing tcp_server_address for client mode")?;
+        let server_port = config.tcp_server_port
+            .ok_or("Missing tcp_server_port for client mode")?;
+        Ok(TcpClient {
+            id: config.id.clone(),
+            config: config.clone(),
+            address: format!("{}:{}", server_addr, server_port),
+            stream: Arc::new(Mutex::new(None)),
+            logger,
+            running: Arc::new(AtomicBool::new(true)),
+        })
+    }
+}
+
+impl Transport for TcpClient {
+    fn describe(&self) -> String {
+        self.address.clone()
+    }
+
+    fn connection(&self, _conn: ConnId) -> String {
+        "Server".to_string()
+    }
+
+    fn client(&self, _conn: ConnId) -> Option<Client> {
+        let stream = self.stream.lock().unwrap();
+        stream.as_ref()?.peer_addr().ok().map(Client::Address)
+    }
+
+    fn start(&self, sink: Sink) -> Result<(), Box<dyn std::error::Error>> {
+        self.logger.log(&format!("[{}] TCP client connecting to {}", self.id, self.address), 5);
+
+        let id = self.id.clone();
+        let config = self.config.clone();
+        let address = self.address.clone();
+        let current = self.stream.clone();
+        let logger = self.logger.clone();
+        let running = self.running.clone();
+
+        thread::spawn(move || {
+            let mut reconnect_delay = Duration::from_secs(1);
+            let max_reconnect_delay = Duration::from_secs(60);
+
+            while running.load(Ordering::SeqCst) {
+                let stream = match TcpStream::connect(&address) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        logger.log(&format!("[{}] Connection failed: {} - retrying in {:?}",
+                            id, e, reconnect_delay), 4);
+                        thread::sleep(reconnect_delay);
+                        reconnect_delay = (reconnect_delay * 2).min(max_reconnect_delay);
+                        continue;
+                    }
+                };
+                logger.log(&format!("[{}] Connected to {}", id, address), 5);
+                reconnect_delay = Duration::from_secs(1);
+
+                *current.lock().unwrap() = stream.try_clone().ok();
+                // stop() may have shut down the connections before this one was stored
+                if !running.load(Ordering::SeqCst) {
+                    break;
+                }
+                sink(Event::Connected(0));
+                let who = format!("[{}] Server", id);
+                let reason = read_stream(stream, 0, &who, &config, &sink, &logger);
+                logger.log(&format!("{} {}", who, reason), if reason == "disconnected" { 6 } else { 4 });
+                sink(Event::Disconnected(0));
+                *current.lock().unwrap() = None;
+            }
+        });
+        Ok(())
+    }
+
+    fn send(&self, _to: Target, unit: Outgoing) -> io::Result<()> {
+        if let Some(ref stream) = *self.stream.lock().unwrap() {
+            (&*stream).write_all(unit.data)?;
+        }
+        sent(unit)
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(ref stream) = *self.stream.lock().unwrap() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// Pty for a local application. The master stays open when the application
+// closes the slave, ready for it to reopen; each open is a new connection.
+#[cfg(unix)]
+pub struct PtyEndpoint {
+    id: String,
+    config: CrossConnect,
+    link: String,
+    pty: Mutex<Option<Arc<crate::pty::Pty>>>,
+    logger: Arc<Logger>,
+    running: Arc<AtomicBool>,
+    reader: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+#[cfg(unix)]
+impl PtyEndpoint {
+    pub fn new(config: &CrossConnect, link: &str, logger: Arc<Logger>) -> Self {
+        PtyEndpoint {
+            id: config.id.clone(),
+            config: config.clone(),
+            link: link.to_string(),
+            pty: Mutex::new(None),
+            logger,
+            running: Arc::new(AtomicBool::new(true)),
+            reader: Mutex::new(None),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Transport for PtyEndpoint {
+    fn describe(&self) -> String {
+        self.link.clone()
+    }
+
+    fn connection(&self, _conn: ConnId) -> String {
+        "Pty application".to_string()
+    }
+
+    fn client(&self, _conn: ConnId) -> Option<Client> {
+        Some(Client::Pty)
+    }
+
+    fn start(&self, sink: Sink) -> Result<(), Box<dyn std::error::Error>> {
+        use crate::pty::{Pty, PtyState};
+
+        let pty = Arc::new(Pty::open(std::path::Path::new(&self.link))
+            .map_err(|e| format!("pty {}: {}", self.link, e))?);
+        self.logger.log(&format!("[{}] Pty {} -> {}", self.id, self.link, pty.slave().display()), 5);
+        *self.pty.lock().unwrap() = Some(pty.clone());
+
+        let id = self.id.clone();
+        let config = self.config.clone();
+        let link = self.link.clone();
+        let logger = self.logger.clone();
+        let running = self.running.clone();
+
+        let handle = thread::spawn(move || {
+            let who = format!("[{}] Pty application", id);
+            let mut buffer = vec![0u8; 4096];
+            let mut framer = StreamFramer::new(&config);
+            let mut attached = false;
+            while running.load(Ordering::SeqCst) {
+                let state = match pty.wait(ACCEPT_POLL_INTERVAL) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        logger.log(&format!("[{}] Pty {} error: {}", id, link, e), 3);
+                        thread::sleep(ACCEPT_POLL_INTERVAL);
+                        continue;
+                    }
+                };
+                if state == PtyState::Closed {
+                    if attached {
+                        logger.log(&format!("[{}] Application closed {}", id, link), 6);
+                        sink(Event::Disconnected(0));
+                        attached = false;
+                    }
+                    thread::sleep(ACCEPT_POLL_INTERVAL);
+                    continue;
+                }
+                if !attached {
+                    logger.log(&format!("[{}] Application opened {}", id, link), 6);
+                    framer = StreamFramer::new(&config);
+                    sink(Event::Connected(0));
+                    attached = true;
+                }
+                if state == PtyState::Readable {
+                    match pty.read(&mut buffer) {
+                        Ok(0) => {}
+                        Ok(n) => {
+                            for unit in framer.push(&buffer[..n], &who, &logger) {
+                                sink(Event::Received(0, unit));
+                            }
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                        Err(e) => logger.log(&format!("[{}] Pty {} read error: {}", id, link, e), 4),
+                    }
+                }
+            }
+        });
+        *self.reader.lock().unwrap() = Some(handle);
+        Ok(())
+    }
+
+    fn send(&self, _to: Target, unit: Outgoing) -> io::Result<()> {
+        if let Some(ref pty) = *self.pty.lock().unwrap() {
+            pty.send(unit.data)?;
+        }
+        sent(unit)
+    }
+
+    // Dropping the pty once its reader has ended removes the link
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(handle) = self.reader.lock().unwrap().take() {
+            let _ = handle.join();
+        }
+        self.pty.lock().unwrap().take();
+    }
+}
+
+// Pty endpoint linked at `link`
+#[cfg(unix)]
+pub fn pty(config: &CrossConnect, link: &str, logger: Arc<Logger>)
+    -> Result<Arc<dyn Transport>, Box<dyn std::error::Error>> {
+    Ok(Arc::new(PtyEndpoint::new(config, link, logger)))
+}
+
+#[cfg(not(unix))]
+pub fn pty(_config: &CrossConnect, _link: &str, _logger: Arc<Logger>)
+    -> Result<Arc<dyn Transport>, Box<dyn std::error::Error>> {
+    Err("pty endpoints are only supported on Unix".into())
+}
+
+// In-memory transport for tests. Events passed to deliver() go to the sink
+// it was started with, and every unit sent to it comes out of the receiver
+// new() returns, with its target and KISS port.
+#[cfg(test)]
+pub mod memory {
+    use std::io;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use super::{sent, ConnId, Event, Outgoing, Sink, Target, Transport};
+    use crate::firewall::Client;
+
+    pub type Sent = (Target, Vec<u8>, Option<u8>);
+
+    pub struct MemoryTransport {
+        clients: Vec<Option<Client>>,    // Who is at each connection
+        sent: Mutex<mpsc::Sender<Sent>>,
+        sink: Mutex<Option<Sink>>,
+    }
+
+    impl MemoryTransport {
+        pub fn new(clients: Vec<Option<Client>>) -> (Arc<Self>, mpsc::Receiver<Sent>) {
+            let (sender, receiver) = mpsc::channel();
+            let transport = MemoryTransport {
+                clients,
+                sent: Mutex::new(sender),
+                sink: Mutex::new(None),
+            };
+            (Arc::new(transport), receiver)
+        }
+
+        pub fn deliver(&self, event: Event) {
+            let sink = self.sink.lock().unwrap().clone().expect("transport not started");
+            sink(event);
+        }
+    }
+
+    impl Transport for MemoryTransport {
+        fn describe(&self) -> String {
+            "memory".to_string()
+        }
+
+        fn connection(&self, conn: ConnId) -> String {
+            format!("Connection {}", conn)
+        }
+
+        fn client(&self, conn: ConnId) -> Option<Client> {
+            self.clients.get(conn).copied().flatten()
+        }
+
+        fn start(&self, sink: Sink) -> Result<(), Box<dyn std::error::Error>> {
+            *self.sink.lock().unwrap() = Some(sink);
+            Ok(())
+        }
+
+        fn send(&self, to: Target, unit: Outgoing) -> io::Result<()> {
+            self.sent.lock().unwrap().send((to, unit.data.to_vec(), unit.port))
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            sent(unit)
+        }
+
+        fn stop(&self) {
+            self.sink.lock().unwrap().take();
+        }
+    }
+}